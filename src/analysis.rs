@@ -0,0 +1,443 @@
+//! Static analysis of template sources using minijinja's parser.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use minijinja::machinery::{ast, parse};
+use serde::{Deserialize, Serialize};
+
+/// Names that resolve without the render context: minijinja's default global
+/// functions, the renderer's own functions and the special variables available
+/// in every template.
+const BUILTIN_NAMES: &[&str] = &[
+    "range",
+    "dict",
+    "debug",
+    "namespace",
+    "render",
+    "component",
+    "self",
+    "super",
+];
+
+/// Filters that return their input sequence with the same element shape.
+const SEQUENCE_FILTERS: &[&str] = &[
+    "sort",
+    "reverse",
+    "list",
+    "unique",
+    "select",
+    "reject",
+    "selectattr",
+    "rejectattr",
+];
+
+/// Filters that return a single element of their input sequence.
+const ELEMENT_FILTERS: &[&str] = &["first", "last"];
+
+/// One step of a path into the render context.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathSegment {
+    /// An attribute or constant string key, e.g. `.label` or `["label"]`.
+    Property(String),
+    /// A constant integer subscript, e.g. `[0]`.
+    Index(usize),
+    /// Any element of a sequence, e.g. a loop variable or a dynamic subscript.
+    Element,
+}
+
+/// A path into the render context, rooted at a top-level variable.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VariablePath(pub Vec<PathSegment>);
+
+impl VariablePath {
+    fn root(name: &str) -> Self {
+        VariablePath(vec![PathSegment::Property(name.to_string())])
+    }
+
+    fn join(&self, segment: PathSegment) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(segment);
+        VariablePath(segments)
+    }
+}
+
+impl fmt::Display for VariablePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                PathSegment::Property(name) if i == 0 => write!(f, "{name}")?,
+                PathSegment::Property(name) => write!(f, ".{name}")?,
+                PathSegment::Index(index) => write!(f, "[{index}]")?,
+                PathSegment::Element => write!(f, "[]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What static analysis learned about a single template.
+#[derive(Debug, Default)]
+pub struct TemplateAnalysis {
+    /// Templates referenced through `include`, `extends`, `import` and `from`.
+    pub dependencies: Vec<String>,
+    /// Every path into the render context the template reads. Loop variables,
+    /// `set` bindings and `with` blocks are traced back to the expressions
+    /// they were bound from.
+    pub variables: BTreeSet<VariablePath>,
+}
+
+/// Parses a template and collects its dependencies and context accesses.
+///
+/// Only dependency names given as string constants can be resolved
+/// statically; dynamic names, multi-candidate lists and
+/// `include ... ignore missing` are skipped.
+pub fn analyze_template(name: &str, source: &str) -> Result<TemplateAnalysis, minijinja::Error> {
+    let root = parse(source, name, Default::default(), Default::default())?;
+    let mut walker = Walker::default();
+    walker.push_scope();
+    for builtin in BUILTIN_NAMES {
+        walker.bind(builtin, Binding::Opaque);
+    }
+    walker.visit_stmt(&root);
+
+    Ok(TemplateAnalysis {
+        dependencies: walker.dependencies.into_iter().collect(),
+        variables: walker.variables,
+    })
+}
+
+/// What a local name refers to.
+#[derive(Clone)]
+enum Binding {
+    /// An alias for a path into the render context.
+    Path(VariablePath),
+    /// A local whose shape can't be traced back to the context, such as a
+    /// macro argument or the result of an arbitrary expression.
+    Opaque,
+}
+
+#[derive(Default)]
+struct Walker {
+    scopes: Vec<HashMap<String, Binding>>,
+    dependencies: BTreeSet<String>,
+    variables: BTreeSet<VariablePath>,
+}
+
+impl Walker {
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn bind(&mut self, name: &str, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), binding);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Binds every name in an assignment target. A plain name aliases
+    /// `value`; names unpacked from a tuple are opaque.
+    fn bind_target(&mut self, target: &ast::Expr, value: Option<VariablePath>) {
+        match target {
+            ast::Expr::Var(var) => {
+                let binding = value.map_or(Binding::Opaque, Binding::Path);
+                self.bind(var.id, binding);
+            }
+            ast::Expr::List(list) => {
+                for item in &list.items {
+                    self.bind_target(item, None);
+                }
+            }
+            // `{% set ns.attr = ... %}` assigns into a namespace object.
+            _ => {}
+        }
+    }
+
+    fn visit_body(&mut self, body: &[ast::Stmt]) {
+        for stmt in body {
+            self.visit_stmt(stmt);
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &ast::Stmt) {
+        match stmt {
+            ast::Stmt::Template(t) => self.visit_body(&t.children),
+            ast::Stmt::EmitExpr(e) => {
+                self.visit_expr(&e.expr);
+            }
+            ast::Stmt::EmitRaw(_) => {}
+            ast::Stmt::ForLoop(f) => {
+                let iter = self.visit_expr(&f.iter);
+                self.push_scope();
+                self.bind("loop", Binding::Opaque);
+                self.bind_target(&f.target, iter.map(|p| p.join(PathSegment::Element)));
+                if let Some(filter_expr) = &f.filter_expr {
+                    self.visit_expr(filter_expr);
+                }
+                self.visit_body(&f.body);
+                self.pop_scope();
+                self.visit_body(&f.else_body);
+            }
+            ast::Stmt::IfCond(i) => {
+                self.visit_expr(&i.expr);
+                self.visit_body(&i.true_body);
+                self.visit_body(&i.false_body);
+            }
+            ast::Stmt::WithBlock(w) => {
+                self.push_scope();
+                for (target, expr) in &w.assignments {
+                    let value = self.visit_expr(expr);
+                    self.bind_target(target, value);
+                }
+                self.visit_body(&w.body);
+                self.pop_scope();
+            }
+            ast::Stmt::Set(s) => {
+                let value = self.visit_expr(&s.expr);
+                self.bind_target(&s.target, value);
+            }
+            ast::Stmt::SetBlock(s) => {
+                if let Some(filter) = &s.filter {
+                    self.visit_expr(filter);
+                }
+                self.visit_body(&s.body);
+                self.bind_target(&s.target, None);
+            }
+            ast::Stmt::AutoEscape(a) => {
+                self.visit_expr(&a.enabled);
+                self.visit_body(&a.body);
+            }
+            ast::Stmt::FilterBlock(f) => {
+                self.visit_expr(&f.filter);
+                self.visit_body(&f.body);
+            }
+            ast::Stmt::Block(b) => self.visit_body(&b.body),
+            ast::Stmt::Import(i) => {
+                self.visit_dependency(&i.expr);
+                self.bind_target(&i.name, None);
+            }
+            ast::Stmt::FromImport(f) => {
+                self.visit_dependency(&f.expr);
+                for (name, alias) in &f.names {
+                    self.bind_target(alias.as_ref().unwrap_or(name), None);
+                }
+            }
+            ast::Stmt::Extends(e) => self.visit_dependency(&e.name),
+            ast::Stmt::Include(i) => {
+                if i.ignore_missing {
+                    self.visit_expr(&i.name);
+                } else {
+                    self.visit_dependency(&i.name);
+                }
+            }
+            ast::Stmt::Macro(m) => {
+                self.bind(m.name, Binding::Opaque);
+                self.visit_macro(m);
+            }
+            ast::Stmt::CallBlock(c) => {
+                self.visit_call(&c.call);
+                self.visit_macro(&c.macro_decl);
+            }
+            ast::Stmt::Do(d) => self.visit_call(&d.call),
+        }
+    }
+
+    fn visit_macro(&mut self, m: &ast::Macro) {
+        for default in &m.defaults {
+            self.visit_expr(default);
+        }
+        self.push_scope();
+        for local in ["varargs", "kwargs", "caller"] {
+            self.bind(local, Binding::Opaque);
+        }
+        for arg in &m.args {
+            self.bind_target(arg, None);
+        }
+        self.visit_body(&m.body);
+        self.pop_scope();
+    }
+
+    /// Records a template name referenced by `include`, `extends`, `import`
+    /// or `from`. Dynamic names are visited as ordinary expressions.
+    fn visit_dependency(&mut self, expr: &ast::Expr) {
+        match expr {
+            ast::Expr::Const(c) => {
+                if let Some(name) = c.value.as_str() {
+                    self.dependencies.insert(name.to_string());
+                }
+            }
+            // `{% include ['a', 'b'] %}` renders the first template that
+            // exists, so only a list with a single candidate is a hard
+            // dependency.
+            ast::Expr::List(l) if l.items.len() == 1 => self.visit_dependency(&l.items[0]),
+            _ => {
+                self.visit_expr(expr);
+            }
+        }
+    }
+
+    fn visit_call(&mut self, call: &ast::Call) {
+        match &call.expr {
+            // A plain name being called is a global function or a macro.
+            ast::Expr::Var(_) => {}
+            // A method call reads the object it's called on.
+            ast::Expr::GetAttr(attr) => {
+                self.visit_expr(&attr.expr);
+            }
+            callee => {
+                self.visit_expr(callee);
+            }
+        }
+        for arg in &call.args {
+            self.visit_expr(arg);
+        }
+    }
+
+    /// Resolves an expression to the context path of its value, if it can be
+    /// traced back to the context.
+    fn resolve_path(&mut self, expr: &ast::Expr) -> Option<VariablePath> {
+        match expr {
+            ast::Expr::Var(var) => match self.lookup(var.id) {
+                Some(Binding::Path(path)) => Some(path.clone()),
+                Some(Binding::Opaque) => None,
+                None => Some(VariablePath::root(var.id)),
+            },
+            ast::Expr::GetAttr(attr) => self
+                .resolve_path(&attr.expr)
+                .map(|path| path.join(PathSegment::Property(attr.name.to_string()))),
+            ast::Expr::GetItem(item) => {
+                let segment = match &item.subscript_expr {
+                    ast::Expr::Const(c) => match c.value.as_str() {
+                        Some(key) => PathSegment::Property(key.to_string()),
+                        None => usize::try_from(c.value.clone())
+                            .map(PathSegment::Index)
+                            .unwrap_or(PathSegment::Element),
+                    },
+                    subscript => {
+                        self.visit_expr(subscript);
+                        PathSegment::Element
+                    }
+                };
+                self.resolve_path(&item.expr).map(|path| path.join(segment))
+            }
+            ast::Expr::Filter(filter) => {
+                let input = filter.expr.as_ref()?;
+                if SEQUENCE_FILTERS.contains(&filter.name) {
+                    self.resolve_path(input)
+                } else if ELEMENT_FILTERS.contains(&filter.name) {
+                    self.resolve_path(input)
+                        .map(|path| path.join(PathSegment::Element))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Records every context path an expression reads and returns the path of
+    /// its value, if it has one.
+    fn visit_expr(&mut self, expr: &ast::Expr) -> Option<VariablePath> {
+        match expr {
+            ast::Expr::Var(_) | ast::Expr::GetAttr(_) | ast::Expr::GetItem(_) => {
+                let path = self.resolve_path(expr);
+                match &path {
+                    // Only the full access is recorded, not each of its
+                    // prefixes.
+                    Some(path) => {
+                        self.variables.insert(path.clone());
+                    }
+                    // The base is a local that can't be traced, but its
+                    // subscripts may still read from the context.
+                    None => self.visit_subscripts(expr),
+                }
+                path
+            }
+            ast::Expr::Filter(filter) => {
+                if let Some(input) = &filter.expr {
+                    self.visit_expr(input);
+                }
+                for arg in &filter.args {
+                    self.visit_expr(arg);
+                }
+                self.resolve_path(expr)
+            }
+            ast::Expr::Const(_) => None,
+            ast::Expr::Slice(s) => {
+                let path = self.visit_expr(&s.expr);
+                for bound in [&s.start, &s.stop, &s.step].into_iter().flatten() {
+                    self.visit_expr(bound);
+                }
+                path
+            }
+            ast::Expr::UnaryOp(u) => {
+                self.visit_expr(&u.expr);
+                None
+            }
+            ast::Expr::BinOp(b) => {
+                self.visit_expr(&b.left);
+                self.visit_expr(&b.right);
+                None
+            }
+            ast::Expr::IfExpr(i) => {
+                self.visit_expr(&i.test_expr);
+                self.visit_expr(&i.true_expr);
+                if let Some(false_expr) = &i.false_expr {
+                    self.visit_expr(false_expr);
+                }
+                None
+            }
+            ast::Expr::Test(t) => {
+                self.visit_expr(&t.expr);
+                for arg in &t.args {
+                    self.visit_expr(arg);
+                }
+                None
+            }
+            ast::Expr::Call(c) => {
+                self.visit_call(c);
+                None
+            }
+            ast::Expr::List(l) => {
+                for item in &l.items {
+                    self.visit_expr(item);
+                }
+                None
+            }
+            ast::Expr::Map(m) => {
+                for expr in m.keys.iter().chain(&m.values) {
+                    self.visit_expr(expr);
+                }
+                None
+            }
+            ast::Expr::Kwargs(k) => {
+                for (_, value) in &k.pairs {
+                    self.visit_expr(value);
+                }
+                None
+            }
+        }
+    }
+
+    fn visit_subscripts(&mut self, expr: &ast::Expr) {
+        match expr {
+            ast::Expr::GetAttr(attr) => self.visit_subscripts(&attr.expr),
+            ast::Expr::GetItem(item) => {
+                self.visit_subscripts(&item.expr);
+                self.visit_expr(&item.subscript_expr);
+            }
+            ast::Expr::Var(_) => {}
+            other => {
+                self.visit_expr(other);
+            }
+        }
+    }
+}
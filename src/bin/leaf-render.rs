@@ -0,0 +1,391 @@
+//! Compiles and renders Leaf templates from the command line.
+//!
+//! Component schemas and template entities are read from JSON files in the
+//! same shapes `register_components` and `compile_templates` accept. On
+//! failure the structured error is written to stderr as JSON and the process
+//! exits non-zero.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+
+use leaf_render::{
+    CompileError, Entity, RegisterError, RegisterRequest, RenderError, Renderer, RendererConfig,
+};
+use serde::{Deserialize, Serialize};
+
+const USAGE: &str = "\
+Usage: leaf-render [OPTIONS]
+
+Options:
+  --components <PATH>          Register component schemas from a JSON file, or
+                               from every .json file in a directory. Each file
+                               holds an array of [component_id, schema, options?]
+  --templates <PATH>           Compile template entities from a JSON file, or
+                               from every .json file in a directory. Each file
+                               holds an array of entities
+  --render <NAME>=<CONTEXT>    Render template NAME with the context in the
+                               JSON file CONTEXT
+  --entity <NAME>=<ENTITY>     Render template NAME for the Leaf entity in the
+                               JSON file ENTITY
+  --out-dir <DIR>              Write each rendered template to DIR/<NAME>
+                               instead of stdout. Required to render more
+                               than one template
+  --validate-context           Validate --render contexts against the
+                               template's component schemas
+  -h, --help                   Print this help
+
+Templates are only compiled and checked if nothing is rendered.";
+
+/// What to render a template with.
+#[derive(Debug, PartialEq)]
+enum Input {
+    Context(PathBuf),
+    Entity(PathBuf),
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    components: Vec<PathBuf>,
+    templates: Vec<PathBuf>,
+    renders: Vec<(String, Input)>,
+    out_dir: Option<PathBuf>,
+    validate_context: bool,
+    help: bool,
+}
+
+/// The JSON written to stderr when the CLI fails.
+#[allow(clippy::enum_variant_names)]
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+enum Failure {
+    UsageError {
+        message: String,
+    },
+    IoError {
+        path: PathBuf,
+        message: String,
+    },
+    /// A JSON file that is malformed or doesn't have the expected shape.
+    ParseError {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    RegisterError {
+        component: String,
+        error: RegisterError,
+    },
+    CompileError {
+        errors: BTreeMap<String, CompileError>,
+    },
+    RenderError {
+        template: String,
+        error: Box<RenderError>,
+    },
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{USAGE}\n");
+            return fail(Failure::UsageError { message });
+        }
+    };
+    if args.help {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => fail(failure),
+    }
+}
+
+fn fail(failure: Failure) -> ExitCode {
+    let code = match failure {
+        Failure::UsageError { .. } | Failure::IoError { .. } => 2,
+        _ => 1,
+    };
+    eprintln!("{}", serde_json::to_string_pretty(&failure).unwrap());
+    ExitCode::from(code)
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} expects a value"));
+        match arg.as_str() {
+            "--components" => parsed.components.push(value()?.into()),
+            "--templates" => parsed.templates.push(value()?.into()),
+            "--render" | "--entity" => {
+                let value = value()?;
+                let Some((name, path)) = value.split_once('=') else {
+                    return Err(format!("{arg} expects <NAME>=<PATH>, got '{value}'"));
+                };
+                let input = if arg == "--render" {
+                    Input::Context(path.into())
+                } else {
+                    Input::Entity(path.into())
+                };
+                parsed.renders.push((name.to_string(), input));
+            }
+            "--out-dir" => parsed.out_dir = Some(value()?.into()),
+            "--validate-context" => parsed.validate_context = true,
+            "-h" | "--help" => parsed.help = true,
+            _ => return Err(format!("Unknown argument '{arg}'")),
+        }
+    }
+    if parsed.renders.len() > 1 && parsed.out_dir.is_none() {
+        // Outputs written one after another to stdout couldn't be told apart.
+        return Err("Rendering more than one template requires --out-dir".to_string());
+    }
+    if parsed.out_dir.is_some() {
+        // Names become paths under --out-dir, so they must stay inside it.
+        let escapes = |name: &str| {
+            name.is_empty()
+                || Path::new(name)
+                    .components()
+                    .any(|component| !matches!(component, Component::Normal(_)))
+        };
+        if let Some((name, _)) = parsed.renders.iter().find(|(name, _)| escapes(name)) {
+            return Err(format!(
+                "Template '{name}' can't be written under --out-dir"
+            ));
+        }
+    }
+    Ok(parsed)
+}
+
+fn run(args: &Args) -> Result<(), Failure> {
+    let mut renderer = Renderer::new();
+    renderer.configure(RendererConfig {
+        validate_context: args.validate_context,
+        ..RendererConfig::default()
+    });
+
+    let mut requests = Vec::new();
+    for path in json_files(&args.components)? {
+        let file = read_json::<Vec<RegisterRequest>>(&path)?;
+        requests.extend(file.into_iter().map(RegisterRequest::into_parts));
+    }
+    // No templates are compiled yet, so registration can only fail on an
+    // invalid schema.
+    for (component, result) in renderer.register_components(requests) {
+        if let Err(error) = result {
+            return Err(Failure::RegisterError { component, error });
+        }
+    }
+
+    let mut entities = Vec::new();
+    for path in json_files(&args.templates)? {
+        entities.extend(read_json::<Vec<Entity>>(&path)?);
+    }
+    renderer
+        .compile_entities(&entities)
+        .map_err(|errors| Failure::CompileError { errors })?;
+
+    for (name, input) in &args.renders {
+        let rendered = match input {
+            Input::Context(path) => renderer.render(name, read_json(path)?),
+            Input::Entity(path) => renderer.render_entity(name, &read_json(path)?),
+        }
+        .map_err(|error| Failure::RenderError {
+            template: name.clone(),
+            error: Box::new(error),
+        })?;
+
+        match &args.out_dir {
+            Some(dir) => {
+                let path = dir.join(name);
+                let written = match path.parent() {
+                    Some(parent) => fs::create_dir_all(parent),
+                    None => Ok(()),
+                }
+                .and_then(|()| fs::write(&path, rendered));
+                written.map_err(|e| io_error(&path, e))?;
+            }
+            None => print!("{rendered}"),
+        }
+    }
+    Ok(())
+}
+
+/// Expands each path into itself, or into the `.json` files directly inside it
+/// if it is a directory, in name order.
+fn json_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Failure> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let entries = fs::read_dir(path).map_err(|e| io_error(path, e))?;
+        let mut found = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| io_error(path, e))?.path();
+            if entry.is_file() && entry.extension().is_some_and(|ext| ext == "json") {
+                found.push(entry);
+            }
+        }
+        found.sort();
+        files.extend(found);
+    }
+    Ok(files)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, Failure> {
+    let bytes = fs::read(path).map_err(|e| io_error(path, e))?;
+    serde_json::from_slice(&bytes).map_err(|e| Failure::ParseError {
+        path: path.to_path_buf(),
+        line: e.line(),
+        column: e.column(),
+        message: e.to_string(),
+    })
+}
+
+fn io_error(path: &Path, error: impl ToString) -> Failure {
+    Failure::IoError {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let parsed = args(&[
+            "--components",
+            "schemas",
+            "--templates",
+            "templates.json",
+            "--render",
+            "page=ctx.json",
+            "--entity",
+            "card=card.json",
+            "--out-dir",
+            "dist",
+            "--validate-context",
+        ])
+        .unwrap();
+        assert_eq!(
+            parsed,
+            Args {
+                components: vec!["schemas".into()],
+                templates: vec!["templates.json".into()],
+                renders: vec![
+                    ("page".to_string(), Input::Context("ctx.json".into())),
+                    ("card".to_string(), Input::Entity("card.json".into())),
+                ],
+                out_dir: Some("dist".into()),
+                validate_context: true,
+                help: false,
+            }
+        );
+
+        assert!(args(&["--render", "page"])
+            .unwrap_err()
+            .contains("<NAME>=<PATH>"));
+        assert!(args(&["--templates"])
+            .unwrap_err()
+            .contains("expects a value"));
+        assert!(args(&["--bogus"]).is_err());
+
+        let error = args(&["--render", "a=a.json", "--entity", "b=b.json"]).unwrap_err();
+        assert!(error.contains("--out-dir"), "{error}");
+        assert!(args(&[
+            "--render",
+            "a=a.json",
+            "--render",
+            "b=b.json",
+            "--out-dir",
+            "dist"
+        ])
+        .is_ok());
+
+        assert!(args(&["--out-dir", "dist", "--render", "pages/home=ctx.json"]).is_ok());
+        for name in [
+            "../escape",
+            "pages/../../escape",
+            "/etc/passwd",
+            "./page",
+            "",
+        ] {
+            let render = format!("{name}=ctx.json");
+            let error = args(&["--out-dir", "dist", "--render", &render]).unwrap_err();
+            assert!(error.contains("--out-dir"), "{name}: {error}");
+        }
+    }
+
+    #[test]
+    fn test_run_reports_compile_errors() {
+        let dir = std::env::temp_dir().join(format!("leaf-render-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let components = dir.join("components.json");
+        let templates = dir.join("templates.json");
+        fs::write(
+            &components,
+            r#"[["greeting", {"type": "object", "properties": {"name": {"type": "string"}}}]]"#,
+        )
+        .unwrap();
+        let entity = |source: &str| {
+            serde_json::json!({
+                leaf_render::TEMPLATE_KEY: {
+                    "name": "hello",
+                    "source": source,
+                    "components": ["greeting"],
+                }
+            })
+        };
+
+        let args = Args {
+            components: vec![components.clone()],
+            templates: vec![templates.clone()],
+            ..Args::default()
+        };
+        fs::write(&templates, "[{\"oops\": }]").unwrap();
+        let Err(Failure::ParseError { line, column, .. }) = run(&args) else {
+            panic!("expected a parse error");
+        };
+        assert_eq!((line, column), (1, 11));
+
+        fs::write(
+            &templates,
+            serde_json::to_vec(&[entity("{{ nope }}")]).unwrap(),
+        )
+        .unwrap();
+        let Err(Failure::CompileError { errors }) = run(&args) else {
+            panic!("expected a compile error");
+        };
+        assert!(errors.contains_key("hello"));
+
+        fs::write(
+            &templates,
+            serde_json::to_vec(&[entity("Hi {{ name }}")]).unwrap(),
+        )
+        .unwrap();
+        let context = dir.join("context.json");
+        fs::write(&context, r#"{"name": "Ada"}"#).unwrap();
+        let out = dir.join("out");
+        let args = Args {
+            renders: vec![("hello".to_string(), Input::Context(context))],
+            out_dir: Some(out.clone()),
+            ..args
+        };
+        assert!(run(&args).is_ok());
+        assert_eq!(fs::read_to_string(out.join("hello")).unwrap(), "Hi Ada");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
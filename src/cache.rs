@@ -0,0 +1,218 @@
+//! An LRU cache of rendered output, keyed by template and context.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hash;
+
+/// How often the render cache was consulted, and what it holds.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// A template name and the hash of the context it was rendered with.
+type Key = (String, u64);
+
+struct Entry {
+    /// The serialized context, so that contexts whose hashes collide don't
+    /// share an output.
+    context: String,
+    output: String,
+    /// The rendered template and every template it includes, extends or
+    /// imports, directly or through other templates.
+    templates: BTreeSet<String>,
+    /// Whether the render called `render()` or `component()`, which pick
+    /// their template at render time.
+    nested: bool,
+    last_used: u64,
+}
+
+pub struct RenderCache {
+    capacity: usize,
+    entries: HashMap<Key, Entry>,
+    /// Every entry's key, by when it was last used.
+    recency: BTreeMap<u64, Key>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl RenderCache {
+    pub fn new(capacity: usize) -> Self {
+        RenderCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// The output cached for `name` rendered with `context`, a canonical
+    /// serialization of the context.
+    pub fn get(&mut self, name: &str, context: &str) -> Option<String> {
+        let key = (name.to_string(), hash::fnv1a([context.as_bytes()]));
+        let Some(entry) = self
+            .entries
+            .get_mut(&key)
+            .filter(|entry| entry.context == context)
+        else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.clock += 1;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = self.clock;
+        let output = entry.output.clone();
+        self.recency.insert(self.clock, key);
+        Some(output)
+    }
+
+    /// Stores a rendered output, evicting the least recently used entry if the
+    /// cache is full.
+    pub fn insert(
+        &mut self,
+        name: &str,
+        context: String,
+        output: String,
+        templates: BTreeSet<String>,
+        nested: bool,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = (name.to_string(), hash::fnv1a([context.as_bytes()]));
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        let entry = Entry {
+            context,
+            output,
+            templates,
+            nested,
+            last_used: self.clock,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Drops every output that rendered one of `templates`. Once any template
+    /// changes, outputs that rendered nested entities are dropped too, since
+    /// the change may alter which template renders them.
+    pub fn invalidate(&mut self, templates: &BTreeSet<String>) {
+        if templates.is_empty() {
+            return;
+        }
+        let stale: Vec<Key> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.nested || !entry.templates.is_disjoint(templates))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            self.remove(&key);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lru_eviction_and_invalidation() {
+        let mut cache = RenderCache::new(2);
+        cache.insert(
+            "a",
+            "{}".to_string(),
+            "A".to_string(),
+            templates(&["a"]),
+            false,
+        );
+        cache.insert(
+            "b",
+            "{}".to_string(),
+            "B".to_string(),
+            templates(&["b", "base"]),
+            false,
+        );
+        assert_eq!(cache.get("a", "{}").as_deref(), Some("A"));
+        // "b" is now the least recently used.
+        cache.insert(
+            "c",
+            "{}".to_string(),
+            "C".to_string(),
+            templates(&["c"]),
+            true,
+        );
+        assert_eq!(cache.get("b", "{}"), None);
+        assert_eq!(cache.get("a", "[]"), None);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                entries: 2
+            }
+        );
+
+        cache.insert(
+            "b",
+            "{}".to_string(),
+            "B".to_string(),
+            templates(&["b", "base"]),
+            false,
+        );
+        cache.invalidate(&templates(&["base"]));
+        assert_eq!(cache.get("b", "{}"), None);
+        // "c" rendered nested entities, so any change drops it.
+        assert_eq!(cache.get("c", "{}"), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_hash_collision_misses() {
+        let mut cache = RenderCache::new(2);
+        cache.insert(
+            "a",
+            "{}".to_string(),
+            "A".to_string(),
+            templates(&["a"]),
+            false,
+        );
+        // Stand in for a different context whose hash collides with `{}`.
+        for entry in cache.entries.values_mut() {
+            entry.context = r#"{"x":1}"#.to_string();
+        }
+        assert_eq!(cache.get("a", "{}"), None);
+        assert_eq!(cache.stats().misses, 1);
+    }
+}
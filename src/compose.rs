@@ -0,0 +1,182 @@
+//! Template functions for rendering child entities from within a template.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use minijinja::value::Value as TemplateValue;
+use minijinja::{Environment, Error, ErrorKind, HtmlEscape, Output, State};
+use serde_json::{Map, Value};
+
+use crate::limits;
+use crate::renderer::{entity_context, ComponentRef, Entity, OutputFormat};
+
+/// How deeply `render` and `component` calls may nest before rendering fails,
+/// so that an entity that (indirectly) contains itself can't recurse forever.
+pub const MAX_COMPONENT_DEPTH: usize = 16;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static NESTED: Cell<bool> = const { Cell::new(false) };
+    /// The format of the output currently being written.
+    static PAGE: Cell<Option<OutputFormat>> = const { Cell::new(None) };
+}
+
+/// Runs `f`, which renders a template producing `page`, so that values are
+/// escaped for `page` wherever they come from.
+pub fn within_page<T>(page: OutputFormat, f: impl FnOnce() -> T) -> T {
+    let outer = PAGE.with(|p| p.replace(Some(page)));
+    let result = f();
+    PAGE.with(|p| p.set(outer));
+    result
+}
+
+/// Runs `f` and reports whether it rendered any nested entities.
+pub fn track_nested<T>(f: impl FnOnce() -> T) -> (T, bool) {
+    let outer = NESTED.with(|n| n.replace(false));
+    let result = f();
+    let nested = NESTED.with(|n| n.replace(outer));
+    (result, nested)
+}
+
+/// Registers the `render(entity)` and `component(id, value)` functions on an
+/// environment, resolving entities against the given template-to-component
+/// mapping, along with a formatter that escapes values for the page.
+///
+/// `render` picks the compiled template whose declared components are the
+/// largest subset of the entity's components, breaking ties by template name.
+/// `component` renders a single component value as if it were an entity with
+/// only that component. Both return safe markup if the template they render
+/// escapes values the way the page does, and a plain string otherwise.
+///
+/// Likewise, a value emitted by a template whose escaping doesn't suit the
+/// page, such as a text template included into an HTML one, is escaped for
+/// the page instead.
+pub fn install(
+    env: &mut Environment<'static>,
+    templates: &HashMap<String, Vec<ComponentRef>>,
+    outputs: &HashMap<String, OutputFormat>,
+) {
+    let mut index: Vec<(String, Vec<String>)> = templates
+        .iter()
+        .filter(|(_, components)| !components.is_empty())
+        .map(|(name, components)| {
+            let ids = components.iter().map(|c| c.id().to_string()).collect();
+            (name.clone(), ids)
+        })
+        .collect();
+    index.sort_by(|(a_name, a_ids), (b_name, b_ids)| {
+        b_ids
+            .len()
+            .cmp(&a_ids.len())
+            .then_with(|| a_name.cmp(b_name))
+    });
+    let index = Arc::new(index);
+    let templates = Arc::new(templates.clone());
+    let outputs = Arc::new(outputs.clone());
+
+    let formatter_outputs = outputs.clone();
+    env.set_formatter(move |out, state, value| {
+        limits::check(state, 1)?;
+        let output = formatter_outputs
+            .get(state.name())
+            .copied()
+            .unwrap_or_default();
+        match PAGE.with(Cell::get) {
+            Some(page) if !output.escapes_for(page) => escape_for(out, page, value),
+            _ => minijinja::escape_formatter(out, state, value),
+        }
+    });
+
+    let (render_index, render_templates, render_outputs) =
+        (index.clone(), templates.clone(), outputs.clone());
+    env.add_function("render", move |state: &State, entity: TemplateValue| {
+        let entity: Entity = match serde_json::to_value(&entity) {
+            Ok(Value::Object(entity)) => entity,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "render() expects an entity object keyed by component ID",
+                ))
+            }
+        };
+        render_entity(
+            state,
+            &render_index,
+            &render_templates,
+            &render_outputs,
+            &entity,
+        )
+    });
+
+    env.add_function(
+        "component",
+        move |state: &State, id: String, value: TemplateValue| {
+            let value = serde_json::to_value(&value)
+                .map_err(|e| Error::new(ErrorKind::InvalidOperation, e.to_string()))?;
+            let mut entity = Map::new();
+            entity.insert(id, value);
+            render_entity(state, &index, &templates, &outputs, &entity)
+        },
+    );
+}
+
+/// Writes `value` escaped for `page`, unless it is already safe.
+fn escape_for(out: &mut Output, page: OutputFormat, value: &TemplateValue) -> Result<(), Error> {
+    let written = match page {
+        _ if value.is_safe() => write!(out, "{value}"),
+        OutputFormat::Json => {
+            let json = serde_json::to_string(value).map_err(|e| {
+                Error::new(ErrorKind::BadSerialization, "unable to format to JSON").with_source(e)
+            })?;
+            out.write_str(&json)
+        }
+        OutputFormat::Text => write!(out, "{value}"),
+        OutputFormat::Html | OutputFormat::Xml | OutputFormat::Markdown => {
+            write!(out, "{}", HtmlEscape(&value.to_string()))
+        }
+    };
+    written.map_err(Error::from)
+}
+
+fn render_entity(
+    state: &State,
+    index: &[(String, Vec<String>)],
+    templates: &HashMap<String, Vec<ComponentRef>>,
+    outputs: &HashMap<String, OutputFormat>,
+    entity: &Entity,
+) -> Result<TemplateValue, Error> {
+    let Some((name, _)) = index
+        .iter()
+        .find(|(_, ids)| ids.iter().all(|id| entity.contains_key(id)))
+    else {
+        let mut ids: Vec<&str> = entity.keys().map(String::as_str).collect();
+        ids.sort_unstable();
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            format!("No template renders components [{}]", ids.join(", ")),
+        ));
+    };
+
+    let depth = DEPTH.with(Cell::get);
+    if depth >= MAX_COMPONENT_DEPTH {
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            format!("Nested components exceed the depth limit of {MAX_COMPONENT_DEPTH}"),
+        ));
+    }
+
+    // Every declared component is present, so building the context can't fail.
+    let ctx = entity_context(&templates[name], entity).unwrap_or_default();
+    let output = outputs.get(name).copied().unwrap_or_default();
+    NESTED.with(|n| n.set(true));
+    DEPTH.with(|d| d.set(depth + 1));
+    let rendered = within_page(output, || limits::render_nested(state, name, &ctx));
+    DEPTH.with(|d| d.set(depth));
+    let page = PAGE.with(Cell::get).unwrap_or(output);
+    if output.escapes_for(page) {
+        Ok(TemplateValue::from_safe_string(rendered?))
+    } else {
+        Ok(TemplateValue::from(rendered?))
+    }
+}
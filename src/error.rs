@@ -0,0 +1,213 @@
+//! Typed errors returned by [`Renderer`](crate::Renderer) methods, which the
+//! wasm exports serialize as-is.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use minijinja::Environment;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum CompileErrorType {
+    ParseError,
+    MissingDependency,
+    CompileError,
+    SchemaValidationError,
+    ComponentCollision,
+    UnknownRenderer,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CompileError {
+    pub error_type: CompileErrorType,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_dependencies: Option<Vec<String>>,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum RenderErrorType {
+    ParseError,
+    RenderError,
+    ContextValidationError,
+    MissingComponent,
+    LimitExceeded,
+    Cancelled,
+    UnknownRenderer,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ContextViolation {
+    pub component: String,
+    pub pointer: String,
+    pub keyword: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RenderError {
+    pub error_type: RenderErrorType,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violations: Option<Vec<ContextViolation>>,
+    /// Components the template declares that the rendered entity lacks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_components: Option<Vec<String>>,
+    /// The [`RenderLimits`](crate::RenderLimits) field that was exceeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<String>,
+    /// The minijinja error kind, e.g. `UndefinedError` or `InvalidOperation`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// The template the failure happened in, which may be an included or
+    /// extended template rather than the one that was rendered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// 1-based character columns of the failing expression on `line`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_start: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_end: Option<usize>,
+    /// The source line containing the failing expression.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excerpt: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub causes: Vec<String>,
+}
+
+impl RenderError {
+    pub(crate) fn new(error_type: RenderErrorType, message: &str) -> Self {
+        RenderError {
+            error_type,
+            message: message.to_string(),
+            violations: None,
+            missing_components: None,
+            limit: None,
+            kind: None,
+            template_name: None,
+            line: None,
+            column_start: None,
+            column_end: None,
+            excerpt: None,
+            causes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn from_template_error(err: &minijinja::Error, env: &Environment) -> Self {
+        let mut error = RenderError::new(RenderErrorType::RenderError, &err.to_string());
+
+        // Failures inside included templates are wrapped (e.g. in a `BadInclude`
+        // error), so report the innermost template error that carries a location.
+        let mut located = err;
+        let mut cause = std::error::Error::source(err);
+        while let Some(next) = cause {
+            error.causes.push(next.to_string());
+            if let Some(inner) = next.downcast_ref::<minijinja::Error>() {
+                if inner.line().is_some() {
+                    located = inner;
+                }
+            }
+            cause = next.source();
+        }
+
+        error.kind = Some(format!("{:?}", located.kind()));
+        error.template_name = located.name().map(|name| name.to_string());
+        error.line = located.line();
+
+        let source = located
+            .template_source()
+            .map(|s| s.to_string())
+            .or_else(|| {
+                located
+                    .name()
+                    .and_then(|name| env.get_template(name).ok())
+                    .map(|tmpl| tmpl.source().to_string())
+            });
+        if let (Some(source), Some(line)) = (source.as_deref(), located.line()) {
+            error.excerpt = source.lines().nth(line - 1).map(|l| l.to_string());
+            if let Some(range) = located.range().filter(|r| r.end <= source.len()) {
+                let line_start = source[..range.start].rfind('\n').map_or(0, |i| i + 1);
+                let line_end = source[range.start..]
+                    .find('\n')
+                    .map_or(source.len(), |i| range.start + i);
+                let end = range.end.min(line_end);
+                error.column_start = Some(source[line_start..range.start].chars().count() + 1);
+                error.column_end = Some(source[line_start..end].chars().count() + 1);
+            }
+        }
+
+        error
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.error_type, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.error_type, self.message)
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// A component registration that was refused.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RegisterError {
+    pub message: String,
+    /// The compiled templates the new schema would have broken, when that was
+    /// the reason.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub broken_templates: BTreeMap<String, CompileError>,
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
+/// A template or component removal that was refused. Nothing was removed.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RemoveError {
+    pub message: String,
+    /// The templates that would have broken, when that was the reason.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependents: Vec<String>,
+}
+
+impl fmt::Display for RemoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RemoveError {}
+
+/// A snapshot that couldn't be restored. The renderer is unchanged.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SnapshotError {
+    pub message: String,
+    /// The templates that failed to compile when the snapshot had to be
+    /// re-validated.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub compile_errors: BTreeMap<String, CompileError>,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SnapshotError {}
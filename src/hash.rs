@@ -0,0 +1,19 @@
+//! Stable content hashes, which unlike `std`'s hashers are the same on every
+//! build and platform, so they can be persisted in snapshots.
+
+use serde::Serialize;
+
+/// FNV-1a over the concatenation of `parts`.
+pub fn fnv1a<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in parts.into_iter().flatten() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Hashes the JSON serialization of `value`.
+pub fn of(value: &impl Serialize) -> u64 {
+    fnv1a([serde_json::to_vec(value).unwrap().as_slice()])
+}
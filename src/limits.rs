@@ -0,0 +1,347 @@
+//! Resource limits that keep a single render from exhausting the instance.
+
+use std::cell::RefCell;
+use std::io;
+use std::sync::Arc;
+
+use minijinja::value::{SeqObject, Value as TemplateValue};
+use minijinja::{Environment, Error, ErrorKind, State};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{RenderError, RenderErrorType};
+
+/// minijinja's own recursion limit, which it also caps configured limits at.
+pub const DEFAULT_RECURSION_LIMIT: usize = 500;
+
+/// Bounds on the work a single render may do. Every limit is off by default,
+/// except that minijinja always caps recursion at 500.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RenderLimits {
+    /// How many instructions a render may execute, including those executed
+    /// by nested `render()` and `component()` calls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<u64>,
+    /// minijinja's recursion budget. Loops and conditionals cost 1 level,
+    /// macro calls 5 and includes 10. Values above 500 are capped at 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_recursion: Option<usize>,
+    /// The longest chain of `include`, `extends` and `import` references the
+    /// rendered template may start. A template that reaches itself through
+    /// such references always exceeds it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_include_depth: Option<usize>,
+    /// How many bytes a render may produce.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<usize>,
+    /// How many milliseconds a render may take. Like cancellation, this is
+    /// checked as the template writes output, emits values, calls `range()`,
+    /// iterates arrays from its context and renders nested entities.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Applies the limits minijinja enforces itself to an environment, and
+/// checks the rest as `range()` produces numbers, so that template code which
+/// writes little output still stops in time. Emitted values are checked by
+/// the formatter [`crate::compose::install`] registers.
+pub fn install(env: &mut Environment<'static>, limits: &RenderLimits) {
+    env.set_fuel(limits.fuel);
+    env.set_recursion_limit(limits.max_recursion.unwrap_or(DEFAULT_RECURSION_LIMIT));
+    env.add_function(
+        "range",
+        |state: &State, lower: u32, upper: Option<u32>, step: Option<u32>| {
+            let numbers = minijinja::functions::range(lower, upper, step)?;
+            check(state, numbers.len())?;
+            Ok(numbers)
+        },
+    );
+}
+
+/// Builds the error for a render that hit `limit`, the name of a
+/// [`RenderLimits`] field.
+pub fn exceeded(limit: &str, value: impl std::fmt::Display) -> RenderError {
+    let mut error = RenderError::new(RenderErrorType::LimitExceeded, "");
+    mark_exceeded(&mut error, limit, value);
+    error
+}
+
+/// Turns a template error into a limit error, keeping its location.
+pub fn mark_exceeded(error: &mut RenderError, limit: &str, value: impl std::fmt::Display) {
+    error.error_type = RenderErrorType::LimitExceeded;
+    error.message = format!("Render exceeded the {limit} limit of {value}");
+    error.limit = Some(limit.to_string());
+}
+
+/// The limit a template error was caused by, if any. Errors from included or
+/// nested templates are wrapped, so the whole chain is searched.
+pub fn exceeded_by(err: &minijinja::Error) -> Option<&'static str> {
+    let mut cause: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(next) = cause {
+        if let Some(err) = next.downcast_ref::<minijinja::Error>() {
+            match err.kind() {
+                ErrorKind::OutOfFuel => return Some("fuel"),
+                ErrorKind::InvalidOperation if err.detail() == Some("recursion limit exceeded") => {
+                    return Some("max_recursion")
+                }
+                _ => {}
+            }
+        }
+        cause = next.source();
+    }
+    None
+}
+
+/// A check the host supplies to cancel a render in progress.
+pub type CancelCheck = dyn Fn() -> bool + Send + Sync;
+
+/// How much work passes between deadline and cancellation checks, since the
+/// cancellation check may cross into the host. A write, an emitted value and
+/// an element read from a context array are one unit each, and so is each
+/// number `range()` produces.
+const CHECK_INTERVAL: usize = 64;
+
+/// Why a render was stopped before it finished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interruption {
+    OutputLimit,
+    Timeout,
+    Cancelled,
+}
+
+/// Fuel accounting for one active render.
+struct Level {
+    /// The fuel its own tracker doesn't count: spent by the renders that
+    /// started it, and by the nested renders it has finished.
+    spent: u64,
+    /// What its own tracker had counted when it was last checked.
+    consumed: u64,
+}
+
+/// The limits a render and the templates it renders nested share.
+struct Guard {
+    fuel: Option<u64>,
+    /// The fuel accounting of each active render, outermost first.
+    levels: Vec<Level>,
+    max_bytes: Option<usize>,
+    /// Bytes held by the writers of every active render.
+    bytes: usize,
+    deadline: Option<f64>,
+    cancel: Option<Arc<CancelCheck>>,
+    /// Work done since the deadline and cancellation were last checked.
+    work: usize,
+    interrupted: Option<Interruption>,
+}
+
+impl Guard {
+    fn tick(&mut self, work: usize) -> Result<(), Interruption> {
+        if self.interrupted.is_none() {
+            self.work += work;
+            if self.work >= CHECK_INTERVAL {
+                self.work = 0;
+                if self.deadline.is_some_and(|deadline| now_ms() >= deadline) {
+                    self.interrupted = Some(Interruption::Timeout);
+                } else if self.cancel.as_ref().is_some_and(|cancel| cancel()) {
+                    self.interrupted = Some(Interruption::Cancelled);
+                }
+            }
+        }
+        self.interrupted.map_or(Ok(()), Err)
+    }
+
+    /// The fuel spent so far by the render `state` belongs to, counting what
+    /// the renders that started it spent.
+    fn spent(&mut self, state: &State) -> u64 {
+        let consumed = state.fuel_levels().map_or(0, |(consumed, _)| consumed);
+        match self.levels.last_mut() {
+            Some(level) => {
+                level.consumed = consumed;
+                level.spent + consumed
+            }
+            None => consumed,
+        }
+    }
+}
+
+thread_local! {
+    static GUARD: RefCell<Option<Guard>> = const { RefCell::new(None) };
+}
+
+fn with_guard<T>(f: impl FnOnce(&mut Guard) -> T) -> Option<T> {
+    GUARD.with(|guard| guard.borrow_mut().as_mut().map(f))
+}
+
+/// Runs a render under `limits`, so that nested renders it starts through
+/// [`render_nested`] draw on the same budgets. Returns why the render was
+/// stopped, if it was.
+pub fn guard<T>(
+    limits: &RenderLimits,
+    cancel: Option<Arc<CancelCheck>>,
+    f: impl FnOnce() -> T,
+) -> (T, Option<Interruption>) {
+    let guard = Guard {
+        fuel: limits.fuel,
+        levels: vec![Level {
+            spent: 0,
+            consumed: 0,
+        }],
+        max_bytes: limits.max_output_bytes,
+        bytes: 0,
+        deadline: limits.timeout_ms.map(|timeout| now_ms() + timeout as f64),
+        cancel,
+        work: 0,
+        interrupted: None,
+    };
+    let outer = GUARD.with(|g| g.replace(Some(guard)));
+    let result = f();
+    let guard = GUARD.with(|g| g.replace(outer));
+    (result, guard.and_then(|guard| guard.interrupted))
+}
+
+/// Records `work` units of work done by the render `state` belongs to. Fails
+/// once the render is interrupted or, counting what its nested renders spent,
+/// out of fuel.
+pub fn check(state: &State, work: usize) -> Result<(), Error> {
+    with_guard(|guard| {
+        if guard.tick(work).is_err() {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "render interrupted",
+            ));
+        }
+        let spent = guard.spent(state);
+        match guard.fuel {
+            Some(fuel) if spent >= fuel => Err(Error::from(ErrorKind::OutOfFuel)),
+            _ => Ok(()),
+        }
+    })
+    .unwrap_or(Ok(()))
+}
+
+/// Renders `name` from within the render `state` belongs to, with whatever
+/// that render has left of its budgets.
+pub fn render_nested(state: &State, name: &str, ctx: &serde_json::Value) -> Result<String, Error> {
+    check(state, 1)?;
+    let template = state.env().get_template(name)?;
+    let Some(spent) = with_guard(|guard| guard.spent(state)) else {
+        return template.render(ctx);
+    };
+
+    // The nested render's own tracker starts from the full budget, so it is
+    // held to what is left by `check`, and by charging what it spent to the
+    // render that started it once it finishes.
+    let mut out = LimitedWriter::default();
+    with_guard(|guard| guard.levels.push(Level { spent, consumed: 0 }));
+    let result = template.render_to_write(polled_context(ctx), &mut out);
+    let out_of_fuel = with_guard(|guard| {
+        let level = guard.levels.pop()?;
+        let consumed = match &result {
+            Ok(state) => state.fuel_levels().map_or(0, |(consumed, _)| consumed),
+            // A failed render's tracker is gone, so it is charged what it was
+            // last seen to have spent, or everything left if it ran out.
+            Err(err) if exceeded_by(err) == Some("fuel") => guard
+                .fuel
+                .map_or(0, |fuel| fuel.saturating_sub(level.spent))
+                .max(level.consumed),
+            Err(_) => level.consumed,
+        };
+        if let Some(parent) = guard.levels.last_mut() {
+            parent.spent += consumed;
+        }
+        guard.fuel.map(|fuel| level.spent + consumed > fuel)
+    })
+    .flatten()
+    .unwrap_or(false);
+    let output = out.finish();
+    result?;
+    if out_of_fuel {
+        return Err(Error::from(ErrorKind::OutOfFuel));
+    }
+    Ok(output)
+}
+
+/// Converts a render context into a template value whose arrays count each
+/// element read as work, so that a loop over context data that writes nothing
+/// still notices the deadline and cancellation. Once the render is
+/// interrupted, the arrays end early, which ends the loops over them.
+pub fn polled_context(value: &serde_json::Value) -> TemplateValue {
+    match value {
+        serde_json::Value::Array(items) => {
+            TemplateValue::from_seq_object(PolledSeq(items.iter().map(polled_context).collect()))
+        }
+        serde_json::Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| (key.clone(), polled_context(value)))
+            .collect::<std::collections::BTreeMap<_, _>>()
+            .into(),
+        value => TemplateValue::from_serialize(value),
+    }
+}
+
+struct PolledSeq(Vec<TemplateValue>);
+
+impl SeqObject for PolledSeq {
+    fn get_item(&self, idx: usize) -> Option<TemplateValue> {
+        let running = with_guard(|guard| guard.tick(1).is_ok()).unwrap_or(true);
+        self.0.get(idx).filter(|_| running).cloned()
+    }
+
+    fn item_count(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Collects rendered output. It fails the write that would take the output
+/// of every active render past `max_output_bytes`, and the writes made once
+/// the deadline has passed or the host cancels the render.
+#[derive(Default)]
+pub struct LimitedWriter {
+    buf: Vec<u8>,
+}
+
+impl LimitedWriter {
+    /// The output, which no longer counts towards `max_output_bytes` since
+    /// whoever takes it writes it again.
+    pub fn finish(self) -> String {
+        with_guard(|guard| guard.bytes -= self.buf.len());
+        // minijinja only writes whole `str`s, so the output is valid UTF-8.
+        String::from_utf8_lossy(&self.buf).into_owned()
+    }
+}
+
+impl io::Write for LimitedWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        with_guard(|guard| {
+            if guard
+                .max_bytes
+                .is_some_and(|max| guard.bytes + data.len() > max)
+            {
+                guard.interrupted.get_or_insert(Interruption::OutputLimit);
+            }
+            guard.tick(1)?;
+            guard.bytes += data.len();
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+        .map_err(|_: Interruption| io::Error::other("render interrupted"))?;
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Milliseconds on a monotonic clock. wasm32 has no clock of its own, so the
+/// host provides one.
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+fn now_ms() -> f64 {
+    crate::wasm::host_now()
+}
+
+#[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+fn now_ms() -> f64 {
+    static START: once_cell::sync::Lazy<std::time::Instant> =
+        once_cell::sync::Lazy::new(std::time::Instant::now);
+    START.elapsed().as_secs_f64() * 1000.0
+}
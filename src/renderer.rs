@@ -0,0 +1,2134 @@
+//! The native renderer: template compilation, component registration and
+//! rendering, independent of the wasm boundary.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use minijinja::{AutoEscape, Environment, UndefinedBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::analysis::{self, PathSegment, VariablePath};
+use crate::cache::{CacheStats, RenderCache};
+use crate::compose;
+use crate::error::{
+    CompileError, CompileErrorType, ContextViolation, RegisterError, RemoveError, RenderError,
+    RenderErrorType, SnapshotError,
+};
+use crate::hash;
+use crate::limits::{self, CancelCheck, Interruption, LimitedWriter, RenderLimits};
+use crate::sanitize::{self, SanitizeConfig};
+use crate::schema::ComponentRegistry;
+use crate::snapshot::{self, Decoded, State};
+
+/// The component holding a template entity's [`TemplateSource`].
+pub const TEMPLATE_KEY: &str = "template:01JVK339CW6Q67VAMXCA7XAK7D";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TemplateSource {
+    pub name: String,
+    pub source: String,
+    pub components: Vec<ComponentRef>,
+    /// What the template produces, which decides how interpolated values are
+    /// escaped. Defaults to HTML.
+    #[serde(default)]
+    pub output: OutputFormat,
+}
+
+impl TemplateSource {
+    /// Reads the template stored on a Leaf entity under [`TEMPLATE_KEY`], or
+    /// returns `None` if the entity doesn't hold one with a name, source and
+    /// components. A template with a malformed component reference, or whose
+    /// `output` isn't a known format, is returned as a `ParseError`, along
+    /// with its name.
+    pub fn from_entity(entity: &Entity) -> Option<Result<Self, (String, CompileError)>> {
+        let template = entity.get(TEMPLATE_KEY).and_then(|v| v.as_object())?;
+        let name = template.get("name").and_then(|v| v.as_str())?;
+        let source = template.get("source").and_then(|v| v.as_str())?;
+        let components = template.get("components").and_then(|v| v.as_array())?;
+        let components = match components
+            .iter()
+            .map(ComponentRef::deserialize)
+            .collect::<Result<_, _>>()
+        {
+            Ok(components) => components,
+            Err(e) => {
+                let error = CompileError {
+                    error_type: CompileErrorType::ParseError,
+                    message: format!("Invalid component reference: {e}"),
+                    missing_dependencies: None,
+                };
+                return Some(Err((name.to_string(), error)));
+            }
+        };
+        let output = match template.get("output") {
+            Some(output) => match OutputFormat::deserialize(output) {
+                Ok(output) => output,
+                Err(e) => {
+                    let error = CompileError {
+                        error_type: CompileErrorType::ParseError,
+                        message: format!("Invalid output format: {e}"),
+                        missing_dependencies: None,
+                    };
+                    return Some(Err((name.to_string(), error)));
+                }
+            },
+            None => OutputFormat::default(),
+        };
+        Some(Ok(TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components,
+            output,
+        }))
+    }
+}
+
+/// The kind of document a template renders.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Values are HTML-escaped.
+    #[default]
+    Html,
+    /// Values are escaped with the same rules as HTML, which cover XML. XML
+    /// isn't HTML, so output sanitization leaves it alone.
+    Xml,
+    /// Values are inserted as-is.
+    Text,
+    /// Values are serialized as JSON.
+    Json,
+    /// Values are HTML-escaped, since Markdown passes inline HTML through to
+    /// the page it is rendered into.
+    Markdown,
+}
+
+impl OutputFormat {
+    fn auto_escape(self) -> AutoEscape {
+        match self {
+            OutputFormat::Html | OutputFormat::Xml | OutputFormat::Markdown => AutoEscape::Html,
+            OutputFormat::Json => AutoEscape::Json,
+            OutputFormat::Text => AutoEscape::None,
+        }
+    }
+
+    /// Whether output in this format can carry HTML into a page, and so is
+    /// sanitized under a policy.
+    fn carries_html(self) -> bool {
+        matches!(self, OutputFormat::Html | OutputFormat::Markdown)
+    }
+
+    /// Whether values this format's templates emit can be written into a
+    /// `page` as they are: the page doesn't escape, or escapes the same way.
+    pub(crate) fn escapes_for(self, page: OutputFormat) -> bool {
+        page.auto_escape() == AutoEscape::None || self.auto_escape() == page.auto_escape()
+    }
+}
+
+/// A component a template declares, and where its properties live in the
+/// render context.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum ComponentRef {
+    /// A bare component ID. Its properties are merged into the top-level
+    /// context namespace alongside every other union-mode component.
+    Union(String),
+    /// `{"id": ..., "as": ...}`. Its properties live under `as` if given and
+    /// under `components.<id>` otherwise.
+    Namespaced {
+        id: String,
+        #[serde(rename = "as", default, skip_serializing_if = "Option::is_none")]
+        alias: Option<String>,
+    },
+}
+
+impl ComponentRef {
+    pub fn id(&self) -> &str {
+        match self {
+            ComponentRef::Union(id) | ComponentRef::Namespaced { id, .. } => id,
+        }
+    }
+
+    /// The path of the component's value in the render context, or `None` for
+    /// union-mode components, which occupy the context root.
+    pub(crate) fn context_path(&self) -> Option<Vec<&str>> {
+        match self {
+            ComponentRef::Union(_) => None,
+            ComponentRef::Namespaced {
+                alias: Some(alias), ..
+            } => Some(vec![alias]),
+            ComponentRef::Namespaced { id, alias: None } => Some(vec!["components", id]),
+        }
+    }
+}
+
+/// A Leaf entity: component values keyed by component ID.
+pub type Entity = Map<String, Value>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RendererConfig {
+    /// Validate the render context against the template's component schemas
+    /// before rendering.
+    #[serde(default)]
+    pub validate_context: bool,
+    /// Sanitize the output of HTML and Markdown templates, and the values
+    /// passed to the `sanitize` filter, with this allowlist.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sanitize: Option<SanitizeConfig>,
+    #[serde(default)]
+    pub limits: RenderLimits,
+    /// How many rendered outputs to keep in an LRU cache keyed by template and
+    /// context. Caching is off when unset or 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_capacity: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RegisterOptions {
+    /// Keep the previous schema if the new one breaks compiled templates.
+    #[serde(default)]
+    pub reject_breaking: bool,
+}
+
+/// `[component_id, schema]`, optionally followed by [`RegisterOptions`]: one
+/// entry of a batch [`Renderer::register_components`] accepts, as the host
+/// and the CLI send it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum RegisterRequest {
+    Plain(String, Value),
+    WithOptions(String, Value, RegisterOptions),
+}
+
+impl RegisterRequest {
+    pub fn into_parts(self) -> (String, Value, RegisterOptions) {
+        match self {
+            RegisterRequest::Plain(name, schema) => (name, schema, RegisterOptions::default()),
+            RegisterRequest::WithOptions(name, schema, options) => (name, schema, options),
+        }
+    }
+}
+
+/// What a compiled template was validated against, so that compiling it
+/// again unchanged can be skipped.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct TemplateBuild {
+    /// The hash of the template's [`TemplateSource`].
+    pub content: u64,
+    /// The version of each declared component's schema.
+    pub schemas: BTreeMap<String, Option<u64>>,
+    /// The content hash of every template it depends on, directly or through
+    /// other templates.
+    pub dependencies: BTreeMap<String, u64>,
+}
+
+/// An isolated template namespace with its own environment and component
+/// registry.
+pub struct Renderer {
+    pub(crate) env: Environment<'static>,
+    pub(crate) components: ComponentRegistry,
+    pub(crate) template_components: HashMap<String, Vec<ComponentRef>>,
+    pub(crate) template_dependencies: HashMap<String, Vec<String>>,
+    /// The context paths each compiled template reads, kept so templates can
+    /// be re-checked when a component schema changes.
+    pub(crate) template_variables: HashMap<String, BTreeSet<VariablePath>>,
+    pub(crate) template_outputs: HashMap<String, OutputFormat>,
+    pub(crate) template_builds: HashMap<String, TemplateBuild>,
+    pub(crate) config: RendererConfig,
+    cancel_check: Option<Arc<CancelCheck>>,
+    cache: Mutex<RenderCache>,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `RenderError` carries the full diagnostic the host reports, so it is returned
+// unboxed to keep it as easy to match on as the wire format.
+#[allow(clippy::result_large_err)]
+impl Renderer {
+    pub fn new() -> Self {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        compose::install(&mut env, &HashMap::new(), &HashMap::new());
+        sanitize::install(&mut env, SanitizeConfig::default());
+        limits::install(&mut env, &RenderLimits::default());
+        Renderer {
+            env,
+            components: ComponentRegistry::new(),
+            template_components: HashMap::new(),
+            template_dependencies: HashMap::new(),
+            template_variables: HashMap::new(),
+            template_outputs: HashMap::new(),
+            template_builds: HashMap::new(),
+            config: RendererConfig::default(),
+            cancel_check: None,
+            cache: Mutex::new(RenderCache::new(0)),
+        }
+    }
+
+    pub fn config(&self) -> &RendererConfig {
+        &self.config
+    }
+
+    /// Applies a configuration. Any cached output is dropped, since it may
+    /// have been rendered under different settings.
+    pub fn configure(&mut self, config: RendererConfig) {
+        sanitize::install(&mut self.env, config.sanitize.clone().unwrap_or_default());
+        limits::install(&mut self.env, &config.limits);
+        self.cache = Mutex::new(RenderCache::new(config.cache_capacity.unwrap_or(0)));
+        self.config = config;
+    }
+
+    /// The render cache's hit and miss counts since the renderer was last
+    /// configured, and how many outputs it holds.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
+    /// Sets a check that is polled while templates render. Once it returns
+    /// `true`, the render in progress stops with a `Cancelled` error. It is
+    /// polled periodically as the template writes output, emits values and
+    /// calls `range()`, so resetting it is up to the caller.
+    pub fn set_cancel_check(&mut self, check: impl Fn() -> bool + Send + Sync + 'static) {
+        self.cancel_check = Some(Arc::new(check));
+    }
+
+    /// Registers a component schema, replacing any previous schema for the
+    /// same ID.
+    ///
+    /// Returns the compiled templates the schema no longer allows, keyed by
+    /// template name. They stay compiled.
+    pub fn register_component(
+        &mut self,
+        id: impl Into<String>,
+        schema: Value,
+    ) -> Result<BTreeMap<String, CompileError>, RegisterError> {
+        let id = id.into();
+        let mut results =
+            self.register_components(vec![(id.clone(), schema, RegisterOptions::default())]);
+        results.remove(&id).unwrap()
+    }
+
+    /// Registers a batch of schemas and then re-checks the compiled templates
+    /// each could affect, returning a result per component ID.
+    ///
+    /// Every schema is registered before any template is re-checked, so
+    /// schemas in the batch can `$ref` each other regardless of order. A
+    /// schema registered with `reject_breaking` that breaks templates is
+    /// rolled back to its previous version. An ID that appears more than once
+    /// in the batch is rejected without registering any of its schemas.
+    #[allow(clippy::type_complexity)]
+    pub fn register_components(
+        &mut self,
+        requests: Vec<(String, Value, RegisterOptions)>,
+    ) -> BTreeMap<String, Result<BTreeMap<String, CompileError>, RegisterError>> {
+        let mut results = BTreeMap::new();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (name, _, _) in &requests {
+            *counts.entry(name.as_str()).or_default() += 1;
+        }
+        let duplicates: BTreeSet<String> = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        let mut registered = Vec::new();
+        for (name, schema, options) in requests {
+            if duplicates.contains(&name) {
+                let error = RegisterError {
+                    message: format!("Component '{name}' appears more than once in the batch"),
+                    broken_templates: BTreeMap::new(),
+                };
+                results.insert(name, Err(error));
+                continue;
+            }
+            let previous = self.components.get(&name).cloned();
+            match self.components.register(name.clone(), schema) {
+                Ok(()) => registered.push((name, previous, options)),
+                Err(e) => {
+                    let error = RegisterError {
+                        message: format!("Invalid schema for component: {}", e),
+                        broken_templates: BTreeMap::new(),
+                    };
+                    results.insert(name, Err(error));
+                }
+            }
+        }
+
+        for (name, previous, options) in registered {
+            self.invalidate_cache(&self.templates_declaring_schema(&name));
+            let broken_templates = self.revalidate_templates(&name);
+            let result = if broken_templates.is_empty() || !options.reject_breaking {
+                Ok(broken_templates)
+            } else {
+                let restored = match previous {
+                    Some(previous) => self.components.register(name.clone(), previous),
+                    None => self.components.unregister(&name).map(|_| ()),
+                };
+                let mut message = format!("The new schema for '{name}' breaks compiled templates");
+                // The previous schema compiled when it was registered, so this
+                // only fails if the registry itself is broken.
+                if let Err(e) = restored {
+                    message.push_str(&format!(", and restoring the previous one failed: {e}"));
+                }
+                Err(RegisterError {
+                    message,
+                    broken_templates,
+                })
+            };
+            results.insert(name, result);
+        }
+        results
+    }
+
+    /// Removes a registered component.
+    ///
+    /// Templates that declare the component, or a component whose schema
+    /// references it, would break, so the removal is refused unless `cascade`
+    /// is set, in which case they are removed as well, along with the
+    /// templates that depend on them. Returns the templates that were removed.
+    pub fn unregister_component(
+        &mut self,
+        id: &str,
+        cascade: bool,
+    ) -> Result<Vec<String>, RemoveError> {
+        if !self.components.contains(id) {
+            return Err(RemoveError {
+                message: format!("Component '{id}' is not registered"),
+                dependents: Vec::new(),
+            });
+        }
+
+        let declaring = self.templates_declaring_schema(id);
+        let dependents: BTreeSet<String> = self
+            .dependent_templates(&declaring)
+            .into_iter()
+            .chain(declaring)
+            .collect();
+        if !dependents.is_empty() && !cascade {
+            return Err(RemoveError {
+                message: format!(
+                    "Templates declare component '{id}' or a component whose schema references it"
+                ),
+                dependents: dependents.into_iter().collect(),
+            });
+        }
+
+        match self.components.unregister(id) {
+            Ok(_) => {
+                self.drop_templates(&dependents);
+                Ok(dependents.into_iter().collect())
+            }
+            Err(e) => Err(RemoveError {
+                message: format!("Failed to recompile remaining schemas: {}", e),
+                dependents: Vec::new(),
+            }),
+        }
+    }
+
+    /// Compiles a batch of templates.
+    ///
+    /// References between templates are resolved statically, so the batch can
+    /// be in any order. Compilation is atomic: if any template fails, none of
+    /// the batch is committed and the error of every failing template is
+    /// returned, keyed by name.
+    ///
+    /// A template that was already compiled is skipped if its source,
+    /// components and output format, the schemas of its components and the
+    /// templates it depends on are all unchanged. Returns the names of the
+    /// templates that were compiled.
+    pub fn compile_templates(
+        &mut self,
+        templates: Vec<TemplateSource>,
+    ) -> Result<Vec<String>, BTreeMap<String, CompileError>> {
+        self.compile_batch(templates, BTreeMap::new())
+    }
+
+    /// Compiles the templates stored on Leaf entities, as read by
+    /// [`TemplateSource::from_entity`], like [`Renderer::compile_templates`].
+    /// Entities that don't hold a template are skipped, and a template with
+    /// an unknown output format fails the batch like any other error.
+    pub fn compile_entities(
+        &mut self,
+        entities: &[Entity],
+    ) -> Result<Vec<String>, BTreeMap<String, CompileError>> {
+        let mut templates = Vec::new();
+        let mut errors = BTreeMap::new();
+        for template in entities.iter().filter_map(TemplateSource::from_entity) {
+            match template {
+                Ok(template) => templates.push(template),
+                Err((name, error)) => {
+                    errors.insert(name, error);
+                }
+            }
+        }
+        self.compile_batch(templates, errors)
+    }
+
+    /// Compiles a batch that already failed with `errors` for templates that
+    /// couldn't be read, so that the rest are still checked.
+    fn compile_batch(
+        &mut self,
+        templates: Vec<TemplateSource>,
+        mut errors: BTreeMap<String, CompileError>,
+    ) -> Result<Vec<String>, BTreeMap<String, CompileError>> {
+        let contents: HashMap<String, u64> = templates
+            .iter()
+            .map(|t| (t.name.clone(), hash::of(t)))
+            .collect();
+        let batch_names: HashSet<String> = contents.keys().chain(errors.keys()).cloned().collect();
+        let templates: Vec<TemplateSource> = templates
+            .into_iter()
+            .filter(|t| !self.is_up_to_date(t, &contents))
+            .collect();
+
+        // Compile into a copy of the environment so that a failing batch
+        // leaves the templates that are currently live untouched.
+        let mut staged = self.env.clone();
+        // Escaping is fixed when a template is added, so the staged
+        // environment needs the batch's output formats up front.
+        let mut outputs = self.template_outputs.clone();
+        outputs.extend(templates.iter().map(|t| (t.name.clone(), t.output)));
+        set_auto_escape(&mut staged, outputs);
+        let mut dependencies = HashMap::new();
+        let mut variables = HashMap::new();
+        for t in &templates {
+            match analysis::analyze_template(&t.name, &t.source) {
+                Ok(analysis) => {
+                    dependencies.insert(t.name.clone(), analysis.dependencies);
+                    variables.insert(t.name.clone(), analysis.variables);
+                }
+                Err(e) => {
+                    let error = CompileError {
+                        error_type: CompileErrorType::ParseError,
+                        message: e.to_string(),
+                        missing_dependencies: None,
+                    };
+                    errors.insert(t.name.clone(), error);
+                }
+            }
+        }
+
+        for index in dependency_order(&templates, &dependencies) {
+            let t = &templates[index];
+            let Some(deps) = dependencies.get(&t.name) else {
+                continue;
+            };
+            let missing: Vec<String> = deps
+                .iter()
+                .filter(|dep| {
+                    !batch_names.contains(dep.as_str()) && self.env.get_template(dep).is_err()
+                })
+                .cloned()
+                .collect();
+            let result = if !missing.is_empty() {
+                Err(CompileError {
+                    error_type: CompileErrorType::MissingDependency,
+                    message: format!("Missing template dependencies: {}", missing.join(", ")),
+                    missing_dependencies: Some(missing),
+                })
+            } else {
+                compile_template(&mut staged, &self.components, t, &variables[&t.name])
+            };
+            if let Err(error) = result {
+                errors.insert(t.name.clone(), error);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        self.env = staged;
+        let rebuilt: BTreeSet<String> = templates.iter().map(|t| t.name.clone()).collect();
+        for t in templates {
+            if let Some(deps) = dependencies.remove(&t.name) {
+                self.template_dependencies.insert(t.name.clone(), deps);
+            }
+            if let Some(vars) = variables.remove(&t.name) {
+                self.template_variables.insert(t.name.clone(), vars);
+            }
+            self.template_outputs.insert(t.name.clone(), t.output);
+            self.template_components.insert(t.name, t.components);
+        }
+        let builds: Vec<(String, TemplateBuild)> = rebuilt
+            .iter()
+            .map(|name| (name.clone(), self.template_build(name, &contents)))
+            .collect();
+        self.template_builds.extend(builds);
+        compose::install(
+            &mut self.env,
+            &self.template_components,
+            &self.template_outputs,
+        );
+        self.invalidate_cache(&rebuilt);
+        Ok(rebuilt.into_iter().collect())
+    }
+
+    /// Removes compiled templates.
+    ///
+    /// Templates that include, extend or import a removed template would
+    /// break, so the removal is refused unless `cascade` is set, in which case
+    /// they are removed as well. Returns every template that was removed.
+    pub fn remove_templates<I>(
+        &mut self,
+        names: I,
+        cascade: bool,
+    ) -> Result<Vec<String>, RemoveError>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let names: BTreeSet<String> = names.into_iter().map(Into::into).collect();
+        let unknown: Vec<&str> = names
+            .iter()
+            .filter(|name| self.env.get_template(name).is_err())
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(RemoveError {
+                message: format!("Unknown templates: {}", unknown.join(", ")),
+                dependents: Vec::new(),
+            });
+        }
+
+        let dependents = self.dependent_templates(&names);
+        if !dependents.is_empty() && !cascade {
+            return Err(RemoveError {
+                message: "Other templates depend on the templates being removed".to_string(),
+                dependents: dependents.into_iter().collect(),
+            });
+        }
+
+        let removed: BTreeSet<String> = names.into_iter().chain(dependents).collect();
+        self.drop_templates(&removed);
+        Ok(removed.into_iter().collect())
+    }
+
+    /// Serializes the component registry and the compiled templates into a
+    /// snapshot that [`Renderer::import_state`] restores. The configuration
+    /// and cancel check aren't included.
+    pub fn export_state(&self) -> Vec<u8> {
+        let mut templates: Vec<TemplateSource> = self
+            .template_components
+            .iter()
+            .filter_map(|(name, components)| {
+                let source = self.env.get_template(name).ok()?.source().to_string();
+                Some(TemplateSource {
+                    name: name.clone(),
+                    source,
+                    components: components.clone(),
+                    output: self.template_outputs.get(name).copied().unwrap_or_default(),
+                })
+            })
+            .collect();
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        snapshot::encode(&State {
+            schemas: self.components.schemas().clone().into_iter().collect(),
+            templates,
+            dependencies: self.template_dependencies.clone().into_iter().collect(),
+            variables: self.template_variables.clone().into_iter().collect(),
+            builds: self.template_builds.clone().into_iter().collect(),
+        })
+    }
+
+    /// Replaces the registry and templates with those of a snapshot taken by
+    /// [`Renderer::export_state`], keeping the current configuration.
+    ///
+    /// A snapshot whose hash matches is restored without re-validation. One
+    /// that was taken by another version of the crate, or corrupted since, is
+    /// registered and compiled from scratch instead. Returns whether it was
+    /// re-validated. On error the renderer is left unchanged.
+    ///
+    /// The hash only detects corruption and version mismatches, not
+    /// tampering, since anyone can recompute it. Import snapshots read back
+    /// from storage that others can write with
+    /// [`Renderer::import_state_validated`] instead.
+    pub fn import_state(&mut self, bytes: &[u8]) -> Result<bool, SnapshotError> {
+        self.restore(bytes, false)
+    }
+
+    /// Like [`Renderer::import_state`], but always registers and compiles the
+    /// snapshot from scratch, whether or not its hash matches.
+    pub fn import_state_validated(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        self.restore(bytes, true).map(|_| ())
+    }
+
+    fn restore(&mut self, bytes: &[u8], revalidate: bool) -> Result<bool, SnapshotError> {
+        let error = |message: String| SnapshotError {
+            message,
+            compile_errors: BTreeMap::new(),
+        };
+        let Decoded { state, trusted } = snapshot::decode(bytes).map_err(error)?;
+        let trusted = trusted && !revalidate;
+        let mut restored = Renderer::new();
+        restored.configure(self.config.clone());
+
+        if trusted {
+            restored.components =
+                ComponentRegistry::from_schemas(state.schemas.into_iter().collect())
+                    .map_err(|e| error(format!("Invalid schema in snapshot: {}", e)))?;
+            let outputs = state.templates.iter().map(|t| (t.name.clone(), t.output));
+            restored.template_outputs = outputs.collect();
+            set_auto_escape(&mut restored.env, restored.template_outputs.clone());
+            for t in state.templates {
+                restored
+                    .env
+                    .add_template_owned(t.name.clone(), t.source)
+                    .map_err(|e| error(format!("Invalid template in snapshot: {}", e)))?;
+                restored.template_components.insert(t.name, t.components);
+            }
+            restored.template_dependencies = state.dependencies.into_iter().collect();
+            restored.template_variables = state.variables.into_iter().collect();
+            restored.template_builds = state.builds.into_iter().collect();
+            compose::install(
+                &mut restored.env,
+                &restored.template_components,
+                &restored.template_outputs,
+            );
+        } else {
+            let requests = state
+                .schemas
+                .into_iter()
+                .map(|(id, schema)| (id, schema, RegisterOptions::default()));
+            for (id, result) in restored.register_components(requests.collect()) {
+                if let Err(e) = result {
+                    return Err(error(format!("Component '{id}': {}", e.message)));
+                }
+            }
+            restored
+                .compile_templates(state.templates)
+                .map_err(|compile_errors| SnapshotError {
+                    message: "Templates in the snapshot failed to compile".to_string(),
+                    compile_errors,
+                })?;
+        }
+
+        restored.cancel_check = self.cancel_check.take();
+        *self = restored;
+        Ok(!trusted)
+    }
+
+    /// Renders a compiled template with the given context, validating it first
+    /// if [`RendererConfig::validate_context`] is set.
+    pub fn render(&self, name: &str, ctx: Value) -> Result<String, RenderError> {
+        self.render_with_context(name, ctx, self.config.validate_context)
+    }
+
+    /// Renders each `(template, context)` pair in turn, as [`Renderer::render`]
+    /// would, returning a result per pair in the same order. A failing render
+    /// doesn't stop the ones after it.
+    pub fn render_many<N>(
+        &self,
+        requests: impl IntoIterator<Item = (N, Value)>,
+    ) -> Vec<Result<String, RenderError>>
+    where
+        N: AsRef<str>,
+    {
+        requests
+            .into_iter()
+            .map(|(name, ctx)| self.render(name.as_ref(), ctx))
+            .collect()
+    }
+
+    /// Renders a template for a Leaf entity, building the context from the
+    /// entity's values for the components the template declares.
+    ///
+    /// Union-mode components have their properties merged into the context
+    /// root, and namespaced components are placed at their own path. Every
+    /// declared component must be present on the entity, and the context is
+    /// always validated against the registered schemas.
+    pub fn render_entity(&self, name: &str, entity: &Entity) -> Result<String, RenderError> {
+        let Some(components) = self.template_components.get(name) else {
+            return Err(RenderError::new(
+                RenderErrorType::ParseError,
+                "Template not found",
+            ));
+        };
+        match entity_context(components, entity) {
+            Ok(ctx) => self.render_with_context(name, ctx, true),
+            Err(missing) => {
+                let mut error = RenderError::new(
+                    RenderErrorType::MissingComponent,
+                    &format!(
+                        "Entity is missing components required by the template: {}",
+                        missing.join(", ")
+                    ),
+                );
+                error.missing_components = Some(missing);
+                Err(error)
+            }
+        }
+    }
+
+    /// Renders a compiled template, optionally validating the context against
+    /// the template's component schemas first.
+    fn render_with_context(
+        &self,
+        name: &str,
+        ctx: Value,
+        validate: bool,
+    ) -> Result<String, RenderError> {
+        if self.config.cache_capacity.unwrap_or(0) == 0 {
+            return self.render_uncached(name, ctx, validate);
+        }
+
+        // serde_json keeps object keys sorted, so equal contexts serialize
+        // identically.
+        let key = serde_json::to_string(&(validate, &ctx)).unwrap();
+        if let Some(output) = self.cache.lock().unwrap().get(name, &key) {
+            return Ok(output);
+        }
+        let (result, nested) = compose::track_nested(|| self.render_uncached(name, ctx, validate));
+        let output = result?;
+        let mut templates = self.template_closure(name);
+        templates.insert(name.to_string());
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(name, key, output.clone(), templates, nested);
+        Ok(output)
+    }
+
+    fn render_uncached(
+        &self,
+        name: &str,
+        ctx: Value,
+        validate: bool,
+    ) -> Result<String, RenderError> {
+        let tmpl = self
+            .env
+            .get_template(name)
+            .map_err(|_| RenderError::new(RenderErrorType::ParseError, "Template not found"))?;
+
+        if validate {
+            let components = self
+                .template_components
+                .get(name)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            if let Err(violations) = validate_context(&self.components, components, &ctx) {
+                let mut error = RenderError::new(
+                    RenderErrorType::ContextValidationError,
+                    "Context does not match the template's component schemas",
+                );
+                error.violations = Some(violations);
+                return Err(error);
+            }
+        }
+
+        let limits = &self.config.limits;
+        if let Some(max) = limits.max_include_depth {
+            if self.include_depth(name).is_none_or(|depth| depth > max) {
+                return Err(limits::exceeded("max_include_depth", max));
+            }
+        }
+
+        let output = self.template_outputs.get(name).copied().unwrap_or_default();
+        let (result, interrupted) = limits::guard(limits, self.cancel_check.clone(), || {
+            let mut out = LimitedWriter::default();
+            compose::within_page(output, || {
+                tmpl.render_to_write(limits::polled_context(&ctx), &mut out)
+            })
+            .map(|_| out.finish())
+        });
+        match interrupted {
+            Some(Interruption::OutputLimit) => {
+                let max = limits.max_output_bytes.unwrap_or_default();
+                return Err(limits::exceeded("max_output_bytes", max));
+            }
+            Some(Interruption::Timeout) => {
+                let max = limits.timeout_ms.unwrap_or_default();
+                return Err(limits::exceeded("timeout_ms", max));
+            }
+            Some(Interruption::Cancelled) => {
+                return Err(RenderError::new(
+                    RenderErrorType::Cancelled,
+                    "Render was cancelled",
+                ));
+            }
+            None => {}
+        }
+        let rendered = result.map_err(|e| {
+            let mut error = RenderError::from_template_error(&e, &self.env);
+            if let Some(limit) = limits::exceeded_by(&e) {
+                let value = match limit {
+                    "fuel" => limits.fuel.unwrap_or_default(),
+                    _ => self.env.recursion_limit() as u64,
+                };
+                limits::mark_exceeded(&mut error, limit, value);
+            }
+            error
+        })?;
+        match &self.config.sanitize {
+            // Other formats aren't HTML, and cleaning them as HTML would drop
+            // their markup or entity-encode their text.
+            Some(policy) if output.carries_html() => Ok(policy.clean(&rendered)),
+            _ => Ok(rendered),
+        }
+    }
+
+    /// The length of the longest chain of template references starting at
+    /// `name`, or `None` if the chain loops back on itself.
+    fn include_depth(&self, name: &str) -> Option<usize> {
+        fn visit<'a>(
+            renderer: &'a Renderer,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+        ) -> Option<usize> {
+            if path.contains(&name) {
+                return None;
+            }
+            path.push(name);
+            let mut depth = 0;
+            for dep in renderer
+                .template_dependencies
+                .get(name)
+                .into_iter()
+                .flatten()
+            {
+                depth = depth.max(visit(renderer, dep, path)? + 1);
+            }
+            path.pop();
+            Some(depth)
+        }
+        visit(self, name, &mut Vec::new())
+    }
+
+    /// Every template `name` depends on, directly or through other templates.
+    fn template_closure(&self, name: &str) -> BTreeSet<String> {
+        let mut closure = BTreeSet::new();
+        let mut pending = vec![name.to_string()];
+        while let Some(template) = pending.pop() {
+            for dep in self
+                .template_dependencies
+                .get(&template)
+                .into_iter()
+                .flatten()
+            {
+                if closure.insert(dep.clone()) {
+                    pending.push(dep.clone());
+                }
+            }
+        }
+        closure
+    }
+
+    /// Every compiled template that depends on one of `names`, directly or
+    /// through other templates, excluding `names` themselves.
+    pub(crate) fn dependent_templates(&self, names: &BTreeSet<String>) -> BTreeSet<String> {
+        let mut dependents = BTreeSet::new();
+        let mut pending: Vec<&String> = names.iter().collect();
+        while let Some(name) = pending.pop() {
+            for (template, deps) in &self.template_dependencies {
+                if deps.contains(name)
+                    && !names.contains(template)
+                    && dependents.insert(template.clone())
+                {
+                    pending.push(template);
+                }
+            }
+        }
+        dependents
+    }
+
+    /// Every compiled template that declares `component`.
+    pub(crate) fn templates_declaring(&self, component: &str) -> BTreeSet<String> {
+        self.template_components
+            .iter()
+            .filter(|(_, components)| components.iter().any(|c| c.id() == component))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Every compiled template that declares `component`, or a component whose
+    /// schema references it.
+    fn templates_declaring_schema(&self, component: &str) -> BTreeSet<String> {
+        self.components
+            .dependents(component)
+            .iter()
+            .flat_map(|affected| self.templates_declaring(affected))
+            .collect()
+    }
+
+    /// Re-checks the compiled templates that could be affected by a change to
+    /// `component`'s schema: those declaring it, or declaring a component whose
+    /// schema references it. Returns the templates that no longer validate.
+    pub(crate) fn revalidate_templates(&self, component: &str) -> BTreeMap<String, CompileError> {
+        let affected = self.components.dependents(component);
+        self.template_components
+            .iter()
+            .filter(|(_, components)| components.iter().any(|c| affected.contains(c.id())))
+            .filter_map(|(name, components)| {
+                let vars = self.template_variables.get(name)?;
+                validate_template_variables(&self.components, components, vars)
+                    .err()
+                    .map(|error| (name.clone(), error))
+            })
+            .collect()
+    }
+
+    /// Whether `template` was compiled before, unchanged, against the schemas
+    /// and dependencies that are current. `contents` holds the content hashes
+    /// of the batch being compiled, which supersede those of earlier builds.
+    fn is_up_to_date(&self, template: &TemplateSource, contents: &HashMap<String, u64>) -> bool {
+        let Some(build) = self.template_builds.get(&template.name) else {
+            return false;
+        };
+        build.content == contents[&template.name]
+            && build.schemas == self.schema_versions(&template.components)
+            && build.dependencies.iter().all(|(dep, content)| {
+                let current = contents
+                    .get(dep)
+                    .or_else(|| self.template_builds.get(dep).map(|build| &build.content));
+                current == Some(content)
+            })
+    }
+
+    /// Records what the compiled template `name` was validated against.
+    fn template_build(&self, name: &str, contents: &HashMap<String, u64>) -> TemplateBuild {
+        let dependencies = self
+            .template_closure(name)
+            .into_iter()
+            .filter(|dep| dep != name)
+            .filter_map(|dep| {
+                let content = contents
+                    .get(&dep)
+                    .or_else(|| self.template_builds.get(&dep).map(|build| &build.content));
+                Some((dep, *content?))
+            })
+            .collect();
+        TemplateBuild {
+            content: contents[name],
+            schemas: self.schema_versions(&self.template_components[name]),
+            dependencies,
+        }
+    }
+
+    fn schema_versions(&self, components: &[ComponentRef]) -> BTreeMap<String, Option<u64>> {
+        components
+            .iter()
+            .map(|c| (c.id().to_string(), self.components.version(c.id())))
+            .collect()
+    }
+
+    fn drop_templates(&mut self, names: &BTreeSet<String>) {
+        for name in names {
+            self.env.remove_template(name);
+            self.template_components.remove(name);
+            self.template_dependencies.remove(name);
+            self.template_variables.remove(name);
+            self.template_outputs.remove(name);
+            self.template_builds.remove(name);
+        }
+        set_auto_escape(&mut self.env, self.template_outputs.clone());
+        compose::install(
+            &mut self.env,
+            &self.template_components,
+            &self.template_outputs,
+        );
+        self.invalidate_cache(names);
+    }
+
+    /// Drops the cached output of `names` and of every template that depends
+    /// on them.
+    fn invalidate_cache(&self, names: &BTreeSet<String>) {
+        let mut stale = self.dependent_templates(names);
+        stale.extend(names.iter().cloned());
+        self.cache.lock().unwrap().invalidate(&stale);
+    }
+}
+
+/// Escapes each template according to its output format. Templates without
+/// one, such as those added outside [`Renderer::compile_templates`], are
+/// treated as HTML.
+fn set_auto_escape(env: &mut Environment<'static>, outputs: HashMap<String, OutputFormat>) {
+    env.set_auto_escape_callback(move |name| {
+        outputs.get(name).copied().unwrap_or_default().auto_escape()
+    });
+}
+
+/// Checks every context path a template reads against its component schemas,
+/// following loop variables and `set`/`with` bindings into array `items`,
+/// `prefixItems` and `additionalProperties`, and through `$ref` and
+/// `allOf`/`anyOf`/`oneOf` composition.
+///
+/// Paths under a namespaced component's prefix are checked against that
+/// component alone. Every other path must be declared by exactly one of the
+/// union-mode components; a path several of them declare is reported as a
+/// collision.
+pub(crate) fn validate_template_variables(
+    registry: &ComponentRegistry,
+    components: &[ComponentRef],
+    vars: &BTreeSet<VariablePath>,
+) -> Result<(), CompileError> {
+    let not_allowed = |var: &VariablePath| CompileError {
+        error_type: CompileErrorType::SchemaValidationError,
+        message: format!("Variable '{var}' is not allowed by schema"),
+        missing_dependencies: None,
+    };
+
+    for var in vars {
+        let namespaced = components.iter().find_map(|component| {
+            let prefix = component.context_path()?;
+            let matches = prefix.len() <= var.0.len()
+                && prefix
+                    .iter()
+                    .zip(&var.0)
+                    .all(|(name, segment)| *segment == PathSegment::Property(name.to_string()));
+            matches.then(|| (component.id(), &var.0[prefix.len()..]))
+        });
+        if let Some((id, rest)) = namespaced {
+            if !rest.is_empty() && !registry.declares_path(id, rest) {
+                return Err(not_allowed(var));
+            }
+            continue;
+        }
+
+        let declaring: Vec<&str> = components
+            .iter()
+            .filter(|component| component.context_path().is_none())
+            .map(ComponentRef::id)
+            .filter(|id| registry.declares_path(id, &var.0))
+            .collect();
+        match declaring.len() {
+            0 => return Err(not_allowed(var)),
+            1 => {}
+            _ => {
+                return Err(CompileError {
+                    error_type: CompileErrorType::ComponentCollision,
+                    message: format!(
+                        "Variable '{var}' is declared by more than one component ({}); \
+                         declare them as namespaced components to disambiguate",
+                        declaring.join(", ")
+                    ),
+                    missing_dependencies: None,
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates the render context against each of the declared component schemas.
+///
+/// Union-mode components describe a top-level Object, and the context namespace
+/// is the union of their properties, so the whole context must satisfy each of
+/// their schemas individually. A namespaced component's schema is checked
+/// against the value at its own path. All violations are collected rather than
+/// stopping at the first.
+pub(crate) fn validate_context(
+    registry: &ComponentRegistry,
+    components: &[ComponentRef],
+    ctx: &Value,
+) -> Result<(), Vec<ContextViolation>> {
+    let mut violations = Vec::new();
+
+    for component in components {
+        let id = component.id();
+        let prefix: String = component
+            .context_path()
+            .unwrap_or_default()
+            .iter()
+            .map(|name| format!("/{}", name.replace('~', "~0").replace('/', "~1")))
+            .collect();
+        let Some(value) = ctx.pointer(&prefix) else {
+            violations.push(ContextViolation {
+                component: id.to_string(),
+                pointer: prefix.clone(),
+                keyword: "required".to_string(),
+                message: format!("Component '{id}' expects a value at '{prefix}'"),
+            });
+            continue;
+        };
+        let Some(state) = registry.validate(id, value) else {
+            violations.push(ContextViolation {
+                component: id.to_string(),
+                pointer: String::new(),
+                keyword: "component".to_string(),
+                message: format!("Component '{id}' is not registered"),
+            });
+            continue;
+        };
+
+        for error in &state.errors {
+            violations.push(ContextViolation {
+                component: id.to_string(),
+                pointer: format!("{prefix}{}", error.get_path()),
+                keyword: error.get_code().to_string(),
+                message: error
+                    .get_detail()
+                    .unwrap_or_else(|| error.get_title())
+                    .to_string(),
+            });
+        }
+        for url in &state.missing {
+            violations.push(ContextViolation {
+                component: id.to_string(),
+                pointer: String::new(),
+                keyword: "$ref".to_string(),
+                message: format!("Unresolved schema reference '{url}'"),
+            });
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+fn compile_template(
+    env: &mut Environment<'static>,
+    registry: &ComponentRegistry,
+    t: &TemplateSource,
+    vars: &BTreeSet<VariablePath>,
+) -> Result<(), CompileError> {
+    match env.add_template_owned(t.name.clone(), t.source.clone()) {
+        Ok(_) => {
+            validate_template_variables(registry, &t.components, vars)?;
+            Ok(())
+        }
+        Err(e) => Err(CompileError {
+            error_type: if e.kind() == minijinja::ErrorKind::SyntaxError {
+                CompileErrorType::ParseError
+            } else {
+                CompileErrorType::CompileError
+            },
+            message: e.to_string(),
+            missing_dependencies: None,
+        }),
+    }
+}
+
+/// Orders the batch so that every template comes after the templates in the
+/// batch it depends on. Dependency cycles are broken at the first revisit.
+fn dependency_order(
+    templates: &[TemplateSource],
+    dependencies: &HashMap<String, Vec<String>>,
+) -> Vec<usize> {
+    fn visit(
+        index: usize,
+        templates: &[TemplateSource],
+        dependencies: &HashMap<String, Vec<String>>,
+        by_name: &HashMap<&str, usize>,
+        visited: &mut HashSet<usize>,
+        order: &mut Vec<usize>,
+    ) {
+        if !visited.insert(index) {
+            return;
+        }
+        for dep in dependencies
+            .get(&templates[index].name)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(&dep_index) = by_name.get(dep.as_str()) {
+                visit(dep_index, templates, dependencies, by_name, visited, order);
+            }
+        }
+        order.push(index);
+    }
+
+    let by_name: HashMap<&str, usize> = templates
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.name.as_str(), i))
+        .collect();
+    let mut visited = HashSet::new();
+    let mut order = Vec::with_capacity(templates.len());
+    for index in 0..templates.len() {
+        visit(
+            index,
+            templates,
+            dependencies,
+            &by_name,
+            &mut visited,
+            &mut order,
+        );
+    }
+    order
+}
+
+/// Builds a render context from an entity's component values, or returns the
+/// IDs of the declared components the entity doesn't have.
+pub(crate) fn entity_context(
+    components: &[ComponentRef],
+    entity: &Entity,
+) -> Result<Value, Vec<String>> {
+    let missing: Vec<String> = components
+        .iter()
+        .map(ComponentRef::id)
+        .filter(|id| !entity.contains_key(*id))
+        .map(str::to_string)
+        .collect();
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+
+    let mut ctx = Map::new();
+    for component in components {
+        let value = entity[component.id()].clone();
+        match component.context_path() {
+            None => {
+                // A non-object value has no properties to merge; context
+                // validation reports it against the component's schema.
+                if let Value::Object(properties) = value {
+                    ctx.extend(properties);
+                }
+            }
+            Some(path) => {
+                let (last, parents) = path.split_last().unwrap();
+                let mut target = &mut ctx;
+                for parent in parents {
+                    let entry = target
+                        .entry(parent.to_string())
+                        .or_insert_with(|| Value::Object(Map::new()));
+                    if !entry.is_object() {
+                        *entry = Value::Object(Map::new());
+                    }
+                    target = entry.as_object_mut().unwrap();
+                }
+                target.insert(last.to_string(), value);
+            }
+        }
+    }
+    Ok(Value::Object(ctx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_native_renderer() {
+        let mut renderer = Renderer::new();
+        let broken = renderer
+            .register_component(
+                "greeting",
+                json!({
+                    "type": "object",
+                    "properties": {"name": {"type": "string"}},
+                    "required": ["name"]
+                }),
+            )
+            .unwrap();
+        assert!(broken.is_empty());
+
+        renderer
+            .compile_templates(vec![TemplateSource {
+                name: "hello".to_string(),
+                source: "Hello {{ name }}!".to_string(),
+                components: vec![ComponentRef::Union("greeting".to_string())],
+                output: OutputFormat::Html,
+            }])
+            .unwrap();
+        assert_eq!(
+            renderer.render("hello", json!({"name": "World"})).unwrap(),
+            "Hello World!"
+        );
+
+        let errors = renderer
+            .compile_templates(vec![TemplateSource {
+                name: "bad".to_string(),
+                source: "{{ missing }}".to_string(),
+                components: vec![ComponentRef::Union("greeting".to_string())],
+                output: OutputFormat::Html,
+            }])
+            .unwrap_err();
+        assert_eq!(
+            errors["bad"].error_type,
+            CompileErrorType::SchemaValidationError
+        );
+
+        renderer.configure(RendererConfig {
+            validate_context: true,
+            ..RendererConfig::default()
+        });
+        let error = renderer.render("hello", json!({"name": 3})).unwrap_err();
+        assert_eq!(error.error_type, RenderErrorType::ContextValidationError);
+    }
+
+    #[test]
+    fn test_output_format_sets_auto_escape() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "text",
+                json!({"type": "object", "properties": {"body": {"type": "string"}}}),
+            )
+            .unwrap();
+        let template = |name: &str, source: &str, output| TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components: vec![ComponentRef::Union("text".to_string())],
+            output,
+        };
+        renderer
+            .compile_templates(vec![
+                template("page", "<p>{{ body }}</p>", OutputFormat::Html),
+                template("plain", "{{ body }}", OutputFormat::Text),
+                template("data", "{\"body\": {{ body }}}", OutputFormat::Json),
+                template("wrapper", "{% include 'plain' %}", OutputFormat::Html),
+            ])
+            .unwrap();
+
+        let ctx = json!({"body": "<b>\"hi\"</b>"});
+        assert_eq!(
+            renderer.render("page", ctx.clone()).unwrap(),
+            "<p>&lt;b&gt;&quot;hi&quot;&lt;&#x2f;b&gt;</p>"
+        );
+        assert_eq!(
+            renderer.render("plain", ctx.clone()).unwrap(),
+            "<b>\"hi\"</b>"
+        );
+        let data: Value =
+            serde_json::from_str(&renderer.render("data", ctx.clone()).unwrap()).unwrap();
+        assert_eq!(data, ctx);
+        // A text template included into an HTML page is escaped for the page.
+        assert_eq!(
+            renderer.render("wrapper", ctx).unwrap(),
+            "&lt;b&gt;&quot;hi&quot;&lt;&#x2f;b&gt;"
+        );
+    }
+
+    #[test]
+    fn test_nested_text_escaped_for_html_page() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "text",
+                json!({"type": "object", "properties": {"body": {"type": "string"}}}),
+            )
+            .unwrap();
+        renderer
+            .register_component(
+                "card",
+                json!({"type": "object", "properties": {"title": {"type": "string"}}}),
+            )
+            .unwrap();
+        let template = |name: &str, source: &str, output| TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components: vec![ComponentRef::Union("text".to_string())],
+            output,
+        };
+        let card = |name: &str, output| TemplateSource {
+            name: name.to_string(),
+            source: "<i>{{ title }}</i>".to_string(),
+            components: vec![ComponentRef::Union("card".to_string())],
+            output,
+        };
+        renderer
+            .compile_templates(vec![
+                card("card", OutputFormat::Text),
+                template(
+                    "page",
+                    "<div>{{ component('card', {'title': body}) }}</div>",
+                    OutputFormat::Html,
+                ),
+                template(
+                    "rendered",
+                    "<div>{{ render({'card': {'title': body} }) }}</div>",
+                    OutputFormat::Html,
+                ),
+                template(
+                    "plain",
+                    "{{ component('card', {'title': body}) }}",
+                    OutputFormat::Text,
+                ),
+            ])
+            .unwrap();
+
+        let ctx = json!({"body": "<script>alert(1)</script>"});
+        let escaped =
+            "<div>&lt;i&gt;&lt;script&gt;alert(1)&lt;&#x2f;script&gt;&lt;&#x2f;i&gt;</div>";
+        assert_eq!(renderer.render("page", ctx.clone()).unwrap(), escaped);
+        assert_eq!(renderer.render("rendered", ctx.clone()).unwrap(), escaped);
+        assert_eq!(
+            renderer.render("plain", ctx.clone()).unwrap(),
+            "<i><script>alert(1)</script></i>"
+        );
+
+        // An HTML card escapes for the page itself, so its markup is kept.
+        renderer
+            .compile_templates(vec![card("card", OutputFormat::Html)])
+            .unwrap();
+        assert_eq!(
+            renderer.render("page", ctx).unwrap(),
+            "<div><i>&lt;script&gt;alert(1)&lt;&#x2f;script&gt;</i></div>"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_output_and_filter() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "post",
+                json!({"type": "object", "properties": {"body": {"type": "string"}}}),
+            )
+            .unwrap();
+        let template = |name: &str, source: &str, output| TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components: vec![ComponentRef::Union("post".to_string())],
+            output,
+        };
+        renderer
+            .compile_templates(vec![
+                template(
+                    "page",
+                    "<p onclick=\"steal()\">{{ body|safe }}</p><script>steal()</script>",
+                    OutputFormat::Html,
+                ),
+                template("filtered", "{{ body|sanitize }}", OutputFormat::Text),
+            ])
+            .unwrap();
+        let ctx = json!({"body": "<a href=\"javascript:steal()\">hi</a><em>there</em>"});
+
+        // Output is only sanitized once a policy is configured.
+        assert!(renderer
+            .render("page", ctx.clone())
+            .unwrap()
+            .contains("<script>"));
+        assert_eq!(
+            renderer.render("filtered", ctx.clone()).unwrap(),
+            "<a rel=\"noopener noreferrer\">hi</a><em>there</em>"
+        );
+
+        renderer.configure(RendererConfig {
+            sanitize: Some(SanitizeConfig::default()),
+            ..RendererConfig::default()
+        });
+        assert_eq!(
+            renderer.render("page", ctx.clone()).unwrap(),
+            "<p><a rel=\"noopener noreferrer\">hi</a><em>there</em></p>"
+        );
+
+        renderer.configure(RendererConfig {
+            sanitize: Some(SanitizeConfig {
+                tags: Some(["p".to_string(), "a".to_string()].into()),
+                url_schemes: Some(["javascript".to_string()].into()),
+                ..SanitizeConfig::default()
+            }),
+            ..RendererConfig::default()
+        });
+        assert_eq!(
+            renderer.render("filtered", ctx).unwrap(),
+            "<a href=\"javascript:steal()\" rel=\"noopener noreferrer\">hi</a>there"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_applies_to_html_and_markdown_output() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "post",
+                json!({"type": "object", "properties": {"body": {"type": "string"}}}),
+            )
+            .unwrap();
+        let template = |name: &str, source: &str, output| TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components: vec![ComponentRef::Union("post".to_string())],
+            output,
+        };
+        renderer
+            .compile_templates(vec![
+                template(
+                    "html",
+                    "<script>x()</script><p onclick=a>{{ body }}</p>",
+                    OutputFormat::Html,
+                ),
+                template(
+                    "feed",
+                    "<rss><channel><item><title>{{ body }}</title></item></channel></rss>",
+                    OutputFormat::Xml,
+                ),
+                template("text", "Tom & Jerry <{{ body }}>", OutputFormat::Text),
+                template(
+                    "markdown",
+                    "<script>x()</script>**{{ body }}** <img src=x onerror=x()>",
+                    OutputFormat::Markdown,
+                ),
+                template("json", "<script>x()</script>{{ body }}", OutputFormat::Json),
+            ])
+            .unwrap();
+        renderer.configure(RendererConfig {
+            sanitize: Some(SanitizeConfig::default()),
+            ..RendererConfig::default()
+        });
+
+        let ctx = json!({"body": "hi"});
+        assert_eq!(renderer.render("html", ctx.clone()).unwrap(), "<p>hi</p>");
+        assert_eq!(
+            renderer.render("feed", ctx.clone()).unwrap(),
+            "<rss><channel><item><title>hi</title></item></channel></rss>"
+        );
+        assert_eq!(
+            renderer.render("text", ctx.clone()).unwrap(),
+            "Tom & Jerry <hi>"
+        );
+        // Markdown passes inline HTML through to the page, so it is cleaned.
+        assert_eq!(
+            renderer.render("markdown", ctx.clone()).unwrap(),
+            "**hi** <img src=\"x\">"
+        );
+        assert_eq!(
+            renderer.render("json", ctx).unwrap(),
+            "<script>x()</script>\"hi\""
+        );
+    }
+
+    #[test]
+    fn test_render_limits() {
+        let mut renderer = Renderer::new();
+        let template = |name: &str, source: &str| TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components: vec![],
+            output: OutputFormat::Text,
+        };
+        renderer
+            .compile_templates(vec![
+                template(
+                    "loop",
+                    "{% for i in range(1000) %}{% for j in range(100) %}x{% endfor %}{% endfor %}",
+                ),
+                template(
+                    "recurse",
+                    "{% macro f(n) %}{{ f(n + 1) }}{% endmacro %}{{ f(0) }}",
+                ),
+                template("a", "{% include 'b' %}"),
+                template("b", "{% include 'c' %}"),
+                template("c", "c"),
+            ])
+            .unwrap();
+        let limit_hit = |renderer: &Renderer, name: &str| {
+            let error = renderer.render(name, json!({})).unwrap_err();
+            assert_eq!(error.error_type, RenderErrorType::LimitExceeded);
+            error.limit.unwrap()
+        };
+
+        // Without limits only minijinja's recursion cap applies.
+        assert_eq!(renderer.render("loop", json!({})).unwrap().len(), 100000);
+        assert_eq!(limit_hit(&renderer, "recurse"), "max_recursion");
+
+        renderer.configure(RendererConfig {
+            limits: RenderLimits {
+                fuel: Some(1000),
+                max_include_depth: Some(1),
+                ..RenderLimits::default()
+            },
+            ..RendererConfig::default()
+        });
+        assert_eq!(limit_hit(&renderer, "loop"), "fuel");
+        assert_eq!(limit_hit(&renderer, "a"), "max_include_depth");
+        assert_eq!(renderer.render("b", json!({})).unwrap(), "c");
+
+        renderer.configure(RendererConfig {
+            limits: RenderLimits {
+                max_output_bytes: Some(10),
+                ..RenderLimits::default()
+            },
+            ..RendererConfig::default()
+        });
+        assert_eq!(limit_hit(&renderer, "loop"), "max_output_bytes");
+        assert_eq!(renderer.render("a", json!({})).unwrap(), "c");
+    }
+
+    #[test]
+    fn test_nested_renders_share_fuel() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "greeting",
+                json!({"type": "object", "properties": {"name": {"type": "string"}}}),
+            )
+            .unwrap();
+        renderer
+            .compile_templates(vec![
+                TemplateSource {
+                    name: "card".to_string(),
+                    source: "{% for i in range(100) %}{{ name }}{% endfor %}".to_string(),
+                    components: vec![ComponentRef::Union("greeting".to_string())],
+                    output: OutputFormat::Text,
+                },
+                TemplateSource {
+                    name: "list".to_string(),
+                    source: "{% for i in range(50) %}{{ component('greeting', {'name': 'x'}) }}{% endfor %}"
+                        .to_string(),
+                    components: vec![],
+                    output: OutputFormat::Text,
+                },
+            ])
+            .unwrap();
+        assert_eq!(renderer.render("list", json!({})).unwrap().len(), 5000);
+
+        renderer.configure(RendererConfig {
+            limits: RenderLimits {
+                fuel: Some(5000),
+                ..RenderLimits::default()
+            },
+            ..RendererConfig::default()
+        });
+        // One card fits the budget, but not fifty of them.
+        assert!(renderer.render("card", json!({"name": "x"})).is_ok());
+        let error = renderer.render("list", json!({})).unwrap_err();
+        assert_eq!(error.error_type, RenderErrorType::LimitExceeded);
+        assert_eq!(error.limit.as_deref(), Some("fuel"));
+    }
+
+    #[test]
+    fn test_render_cancellation_and_timeout() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        let mut renderer = Renderer::new();
+        renderer
+            .compile_templates(vec![TemplateSource {
+                name: "loop".to_string(),
+                source: "{% for i in range(1000) %}{{ i }}{% endfor %}".to_string(),
+                components: vec![],
+                output: OutputFormat::Text,
+            }])
+            .unwrap();
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let polls = Arc::new(AtomicUsize::new(0));
+        let (flag, counter) = (cancelled.clone(), polls.clone());
+        renderer.set_cancel_check(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            flag.load(Ordering::Relaxed)
+        });
+        assert!(renderer.render("loop", json!({})).is_ok());
+        // The check is polled periodically rather than on every write.
+        let polled = polls.load(Ordering::Relaxed);
+        assert!(polled > 1 && polled < 100, "polled {polled} times");
+
+        cancelled.store(true, Ordering::Relaxed);
+        let error = renderer.render("loop", json!({})).unwrap_err();
+        assert_eq!(error.error_type, RenderErrorType::Cancelled);
+
+        cancelled.store(false, Ordering::Relaxed);
+        renderer.configure(RendererConfig {
+            limits: RenderLimits {
+                timeout_ms: Some(0),
+                ..RenderLimits::default()
+            },
+            ..RendererConfig::default()
+        });
+        let error = renderer.render("loop", json!({})).unwrap_err();
+        assert_eq!(error.error_type, RenderErrorType::LimitExceeded);
+        assert_eq!(error.limit.as_deref(), Some("timeout_ms"));
+    }
+
+    #[test]
+    fn test_cancel_loop_that_writes_nothing() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "list",
+                json!({"type": "object", "properties": {"items": {"type": "array", "items": {}}}}),
+            )
+            .unwrap();
+        renderer
+            .compile_templates(vec![TemplateSource {
+                name: "quiet".to_string(),
+                source: "{% for x in items %}{% set y = x %}{% endfor %}".to_string(),
+                components: vec![ComponentRef::Union("list".to_string())],
+                output: OutputFormat::Text,
+            }])
+            .unwrap();
+        let ctx = json!({"items": vec![0; 10000]});
+        assert_eq!(renderer.render("quiet", ctx.clone()).unwrap(), "");
+
+        // Iterating the context array polls the check, so the loop stops
+        // even though it never writes.
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        renderer.set_cancel_check(move || counter.fetch_add(1, Ordering::Relaxed) > 0);
+        let error = renderer.render("quiet", ctx).unwrap_err();
+        assert_eq!(error.error_type, RenderErrorType::Cancelled);
+        assert_eq!(polls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_limits_checked_during_evaluation() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let busy = "{% for i in range(10000) %}{% for j in range(10000) %}\
+                    {% set x = j %}{% endfor %}{% endfor %}";
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "greeting",
+                json!({"type": "object", "properties": {"name": {"type": "string"}}}),
+            )
+            .unwrap();
+        renderer
+            .compile_templates(vec![
+                TemplateSource {
+                    name: "card".to_string(),
+                    source: busy.to_string(),
+                    components: vec![ComponentRef::Union("greeting".to_string())],
+                    output: OutputFormat::Text,
+                },
+                TemplateSource {
+                    name: "busy".to_string(),
+                    source: busy.to_string(),
+                    components: vec![],
+                    output: OutputFormat::Text,
+                },
+                TemplateSource {
+                    name: "list".to_string(),
+                    source: "{{ component('greeting', {'name': 'x'}) }}".to_string(),
+                    components: vec![],
+                    output: OutputFormat::Text,
+                },
+            ])
+            .unwrap();
+        let limit_hit = |renderer: &Renderer, name: &str| {
+            let started = std::time::Instant::now();
+            let error = renderer.render(name, json!({})).unwrap_err();
+            assert!(started.elapsed().as_secs() < 1, "{name} ran too long");
+            assert_eq!(error.error_type, RenderErrorType::LimitExceeded);
+            error.limit.unwrap()
+        };
+
+        renderer.configure(RendererConfig {
+            limits: RenderLimits {
+                timeout_ms: Some(5),
+                ..RenderLimits::default()
+            },
+            ..RendererConfig::default()
+        });
+        // Neither writes any output before the deadline passes.
+        assert_eq!(limit_hit(&renderer, "busy"), "timeout_ms");
+        assert_eq!(limit_hit(&renderer, "list"), "timeout_ms");
+
+        // Nested renders answer to the outer render's cancel check.
+        renderer.configure(RendererConfig::default());
+        let cancelled = Arc::new(AtomicBool::new(true));
+        let flag = cancelled.clone();
+        renderer.set_cancel_check(move || flag.load(Ordering::Relaxed));
+        let error = renderer.render("list", json!({})).unwrap_err();
+        assert_eq!(error.error_type, RenderErrorType::Cancelled);
+    }
+
+    #[test]
+    fn test_unregister_referenced_component() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "b",
+                json!({"$defs": {"x": {"type": "object", "properties": {"y": {"type": "string"}}}}}),
+            )
+            .unwrap();
+        renderer
+            .register_component(
+                "a",
+                json!({"type": "object", "properties": {"x": {"$ref": "b#/$defs/x"}}}),
+            )
+            .unwrap();
+        renderer
+            .compile_templates(vec![TemplateSource {
+                name: "ta".to_string(),
+                source: "{{ x.y }}".to_string(),
+                components: vec![ComponentRef::Union("a".to_string())],
+                output: OutputFormat::Text,
+            }])
+            .unwrap();
+
+        let error = renderer.unregister_component("b", false).unwrap_err();
+        assert_eq!(error.dependents, vec!["ta"]);
+        assert!(renderer.components.contains("b"));
+        assert_eq!(
+            renderer.unregister_component("b", true).unwrap(),
+            vec!["ta"]
+        );
+        assert!(renderer.env.get_template("ta").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "greeting",
+                json!({"type": "object", "properties": {"name": {"type": "string"}}}),
+            )
+            .unwrap();
+        let template = |name: &str, source: &str, output| TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components: vec![ComponentRef::Union("greeting".to_string())],
+            output,
+        };
+        renderer
+            .compile_templates(vec![
+                template("page", "<p>{% include 'hello' %}</p>", OutputFormat::Html),
+                template("hello", "Hi {{ name }}", OutputFormat::Html),
+                template("raw", "{{ name }}", OutputFormat::Text),
+            ])
+            .unwrap();
+        let snapshot = renderer.export_state();
+        assert_eq!(snapshot, renderer.export_state());
+
+        let mut restored = Renderer::new();
+        assert_eq!(restored.import_state(&snapshot), Ok(false));
+        let ctx = json!({"name": "<b>"});
+        assert_eq!(
+            restored.render("page", ctx.clone()).unwrap(),
+            "<p>Hi &lt;b&gt;</p>"
+        );
+        assert_eq!(restored.render("raw", ctx.clone()).unwrap(), "<b>");
+        let entity = Entity::from_iter([("greeting".to_string(), json!({"name": "Ada"}))]);
+        assert_eq!(restored.render_entity("hello", &entity).unwrap(), "Hi Ada");
+        // Restored templates are still checked when a schema changes.
+        let broken = restored
+            .register_component("greeting", json!({"type": "object"}))
+            .unwrap();
+        assert_eq!(broken.keys().collect::<Vec<_>>(), ["hello", "raw"]);
+
+        // A snapshot that doesn't match its hash is compiled from scratch.
+        let mut tampered = snapshot.clone();
+        tampered[8] ^= 1;
+        let mut revalidated = Renderer::new();
+        assert_eq!(revalidated.import_state(&tampered), Ok(true));
+        assert_eq!(revalidated.render("raw", ctx).unwrap(), "<b>");
+
+        let mut bad = snapshot.clone();
+        bad[8] ^= 1;
+        let text = String::from_utf8(bad[16..].to_vec()).unwrap();
+        let bad = [
+            &bad[..16],
+            text.replace("Hi {{ name }}", "Hi {{ nope }}").as_bytes(),
+        ]
+        .concat();
+        let error = revalidated.import_state(&bad).unwrap_err();
+        assert!(error.compile_errors.contains_key("hello"));
+        assert!(revalidated.import_state(b"garbage").is_err());
+        // A failed import leaves the renderer as it was.
+        assert_eq!(revalidated.export_state(), snapshot);
+    }
+
+    #[test]
+    fn test_validated_import_rejects_forged_snapshot() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "greeting",
+                json!({"type": "object", "properties": {"name": {"type": "string"}}}),
+            )
+            .unwrap();
+        renderer
+            .compile_templates(vec![TemplateSource {
+                name: "hello".to_string(),
+                source: "Hi {{ name }}".to_string(),
+                components: vec![ComponentRef::Union("greeting".to_string())],
+                output: OutputFormat::Html,
+            }])
+            .unwrap();
+        let snapshot = renderer.export_state();
+
+        // Rewriting the payload and recomputing the hash yields a snapshot
+        // whose hash matches.
+        let mut state = snapshot::decode(&snapshot).unwrap().state;
+        state.templates[0].source = "Hi {{ nope }}".to_string();
+        let forged = snapshot::encode(&state);
+        assert_eq!(Renderer::new().import_state(&forged), Ok(false));
+
+        let mut validated = Renderer::new();
+        let error = validated.import_state_validated(&forged).unwrap_err();
+        assert!(error.compile_errors.contains_key("hello"));
+        assert_eq!(validated.import_state_validated(&snapshot), Ok(()));
+        assert_eq!(
+            validated.render("hello", json!({"name": "Ada"})).unwrap(),
+            "Hi Ada"
+        );
+    }
+
+    #[test]
+    fn test_incremental_compile() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "greeting",
+                json!({"type": "object", "properties": {"name": {"type": "string"}}}),
+            )
+            .unwrap();
+        let template = |name: &str, source: &str| TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components: vec![ComponentRef::Union("greeting".to_string())],
+            output: OutputFormat::Html,
+        };
+        let page = template("page", "<p>{% include 'hello' %}</p>");
+        let hello = template("hello", "Hi {{ name }}");
+        let other = template("other", "{{ name }}");
+        let batch = vec![page.clone(), hello.clone(), other.clone()];
+
+        assert_eq!(
+            renderer.compile_templates(batch.clone()).unwrap(),
+            ["hello", "other", "page"]
+        );
+        assert!(renderer.compile_templates(batch).unwrap().is_empty());
+
+        // Templates that depend on a changed template are rebuilt with it.
+        let hello = template("hello", "Hello {{ name }}");
+        assert_eq!(
+            renderer
+                .compile_templates(vec![page.clone(), hello.clone(), other.clone()])
+                .unwrap(),
+            ["hello", "page"]
+        );
+        let hello = template("hello", "Hey {{ name }}");
+        assert_eq!(
+            renderer.compile_templates(vec![hello.clone()]).unwrap(),
+            ["hello"]
+        );
+        assert_eq!(
+            renderer.compile_templates(vec![page.clone()]).unwrap(),
+            ["page"]
+        );
+
+        // So are templates whose component schemas changed since.
+        renderer
+            .register_component(
+                "greeting",
+                json!({"type": "object", "properties": {"name": {"type": "string"}, "age": {}}}),
+            )
+            .unwrap();
+        assert_eq!(
+            renderer.compile_templates(vec![other.clone()]).unwrap(),
+            ["other"]
+        );
+
+        // Build records survive a snapshot.
+        let mut restored = Renderer::new();
+        restored.import_state(&renderer.export_state()).unwrap();
+        assert_eq!(
+            restored
+                .compile_templates(vec![page.clone(), hello, other])
+                .unwrap(),
+            ["hello", "page"]
+        );
+        assert_eq!(
+            restored.render("page", json!({"name": "Ada"})).unwrap(),
+            "<p>Hey Ada</p>"
+        );
+
+        renderer.remove_templates(["page"], false).unwrap();
+        assert_eq!(renderer.compile_templates(vec![page]).unwrap(), ["page"]);
+    }
+
+    #[test]
+    fn test_render_cache() {
+        let mut renderer = Renderer::new();
+        renderer.configure(RendererConfig {
+            cache_capacity: Some(8),
+            ..RendererConfig::default()
+        });
+        let schema = json!({"type": "object", "properties": {"name": {"type": "string"}}});
+        renderer
+            .register_component("greeting", schema.clone())
+            .unwrap();
+        let template = |name: &str, source: &str| TemplateSource {
+            name: name.to_string(),
+            source: source.to_string(),
+            components: vec![ComponentRef::Union("greeting".to_string())],
+            output: OutputFormat::Html,
+        };
+        renderer
+            .compile_templates(vec![
+                template("page", "<p>{% include 'hello' %}</p>"),
+                template("hello", "Hi {{ name }}"),
+                template("other", "{{ name }}"),
+            ])
+            .unwrap();
+
+        let ada = json!({"name": "Ada"});
+        assert_eq!(
+            renderer.render("page", ada.clone()).unwrap(),
+            "<p>Hi Ada</p>"
+        );
+        assert_eq!(
+            renderer.render("page", ada.clone()).unwrap(),
+            "<p>Hi Ada</p>"
+        );
+        renderer.render("other", ada.clone()).unwrap();
+        assert!(renderer.render("other", json!({})).is_err());
+        assert_eq!(
+            renderer.cache_stats(),
+            CacheStats {
+                hits: 1,
+                misses: 3,
+                entries: 2
+            }
+        );
+
+        // Recompiling an included template drops the output that included it.
+        renderer
+            .compile_templates(vec![template("hello", "Hello {{ name }}")])
+            .unwrap();
+        assert_eq!(renderer.cache_stats().entries, 1);
+        assert_eq!(
+            renderer.render("page", ada.clone()).unwrap(),
+            "<p>Hello Ada</p>"
+        );
+
+        // So does re-registering a declared component's schema.
+        renderer.register_component("greeting", schema).unwrap();
+        assert_eq!(renderer.cache_stats().entries, 0);
+
+        renderer.configure(RendererConfig::default());
+        renderer.render("page", ada).unwrap();
+        assert_eq!(renderer.cache_stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn test_render_many() {
+        let mut renderer = Renderer::new();
+        renderer
+            .register_component(
+                "greeting",
+                json!({"type": "object", "properties": {"name": {"type": "string"}}}),
+            )
+            .unwrap();
+        renderer
+            .compile_templates(vec![TemplateSource {
+                name: "hello".to_string(),
+                source: "Hi {{ name }}".to_string(),
+                components: vec![ComponentRef::Union("greeting".to_string())],
+                output: OutputFormat::Text,
+            }])
+            .unwrap();
+
+        let results = renderer.render_many([
+            ("hello", json!({"name": "Ada"})),
+            ("missing", json!({})),
+            ("hello", json!({})),
+            ("hello", json!({"name": "Grace"})),
+        ]);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap(), "Hi Ada");
+        assert_eq!(
+            results[1].as_ref().unwrap_err().message,
+            "Template not found"
+        );
+        assert_eq!(
+            results[2].as_ref().unwrap_err().error_type,
+            RenderErrorType::RenderError
+        );
+        assert_eq!(results[3].as_ref().unwrap(), "Hi Grace");
+    }
+}
@@ -0,0 +1,83 @@
+//! Allowlist-based HTML sanitization of rendered output.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use minijinja::value::Value as TemplateValue;
+use minijinja::Environment;
+use serde::{Deserialize, Serialize};
+
+/// Tags whose content is dropped along with the tag unless they are allowed.
+const CLEAN_CONTENT_TAGS: &[&str] = &["script", "style"];
+
+/// Which tags, attributes and URL schemes sanitized HTML may keep.
+///
+/// Each list left unset falls back to ammonia's defaults, which allow common
+/// formatting and structural markup, no scripts, styles or event handlers, and
+/// only the usual URL schemes such as `http`, `https` and `mailto`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SanitizeConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<BTreeSet<String>>,
+    /// Attributes allowed on every tag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generic_attributes: Option<BTreeSet<String>>,
+    /// Attributes allowed on specific tags, keyed by tag name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_attributes: Option<BTreeMap<String, BTreeSet<String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_schemes: Option<BTreeSet<String>>,
+}
+
+impl SanitizeConfig {
+    /// Removes every tag, attribute and URL the allowlists don't admit.
+    pub fn clean(&self, html: &str) -> String {
+        let mut builder = ammonia::Builder::default();
+        if let Some(tags) = &self.tags {
+            builder.tags(tags.iter().map(String::as_str).collect());
+        }
+        if let Some(attributes) = &self.generic_attributes {
+            builder.generic_attributes(attributes.iter().map(String::as_str).collect());
+        }
+        if let Some(tag_attributes) = &self.tag_attributes {
+            builder.tag_attributes(
+                tag_attributes
+                    .iter()
+                    .map(|(tag, attributes)| {
+                        let attributes = attributes.iter().map(String::as_str).collect();
+                        (tag.as_str(), attributes)
+                    })
+                    .collect::<HashMap<_, HashSet<_>>>(),
+            );
+        }
+        if let Some(schemes) = &self.url_schemes {
+            builder.url_schemes(schemes.iter().map(String::as_str).collect());
+        }
+
+        // ammonia panics on contradictory settings, so an explicitly allowed
+        // tag or attribute takes precedence over its built-in handling.
+        let allowed_tags = builder.clone_tags();
+        let tag_attributes = builder.clone_tag_attributes();
+        builder.clean_content_tags(
+            CLEAN_CONTENT_TAGS
+                .iter()
+                .copied()
+                .filter(|tag| !allowed_tags.contains(tag) && !tag_attributes.contains_key(tag))
+                .collect(),
+        );
+        let rel_allowed = builder.clone_generic_attributes().contains("rel")
+            || tag_attributes.get("a").is_some_and(|a| a.contains("rel"));
+        if rel_allowed {
+            builder.link_rel(None);
+        }
+
+        builder.clean(html).to_string()
+    }
+}
+
+/// Registers the `sanitize` filter, which cleans a value with `config` and
+/// marks the result safe.
+pub fn install(env: &mut Environment<'static>, config: SanitizeConfig) {
+    env.add_filter("sanitize", move |value: String| {
+        TemplateValue::from_safe_string(config.clean(&value))
+    });
+}
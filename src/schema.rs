@@ -0,0 +1,424 @@
+//! Component schemas and lookups into them.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_json::Value;
+use url::Url;
+use valico::json_schema::{self, helpers, ValidationState};
+
+use crate::analysis::PathSegment;
+use crate::hash;
+
+/// How many `$ref` and composition hops a single lookup follows before giving
+/// up, so that recursive schemas terminate.
+const MAX_RESOLUTION_DEPTH: usize = 64;
+
+const COMPOSITION_KEYWORDS: &[&str] = &["allOf", "anyOf", "oneOf"];
+
+/// Keywords that describe a schema without constraining it.
+const ANNOTATION_KEYWORDS: &[&str] = &["title", "description", "$comment", "default", "examples"];
+
+/// The schemas registered on a renderer, compiled into a shared valico
+/// [`json_schema::Scope`] so that `$ref`s resolve across components.
+///
+/// Each component is compiled under `leaf://component/<id>`, which is also the
+/// base URL its relative `$ref`s resolve against. The raw schemas are kept
+/// alongside the scope because valico doesn't expose them once compiled.
+pub struct ComponentRegistry {
+    scope: json_schema::Scope,
+    schemas: HashMap<String, Value>,
+    /// Maps each document URL (a component URL or a nested `$id`) to the
+    /// component it belongs to and the JSON pointer to its schema there.
+    documents: HashMap<String, (String, String)>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        ComponentRegistry {
+            scope: json_schema::Scope::new(),
+            schemas: HashMap::new(),
+            documents: HashMap::new(),
+        }
+    }
+
+    /// The URL a component's schema is compiled under.
+    pub fn component_url(id: &str) -> Url {
+        Url::parse("leaf://component/")
+            .unwrap()
+            .join(&helpers::encode(id))
+            .unwrap()
+    }
+
+    /// Compiles and registers a component schema, replacing any previous
+    /// schema for the same component. The registry is unchanged on error.
+    pub fn register(&mut self, id: String, schema: Value) -> Result<(), String> {
+        if self.schemas.contains_key(&id) {
+            // valico can't replace a compiled schema, so recompile everything
+            // into a fresh scope.
+            let mut schemas = self.schemas.clone();
+            schemas.insert(id, schema);
+            self.scope = compile_scope(&schemas)?;
+            self.schemas = schemas;
+        } else {
+            self.scope
+                .compile_with_id(&Self::component_url(&id), schema.clone(), false)
+                .map_err(|e| e.to_string())?;
+            self.schemas.insert(id, schema);
+        }
+        self.index_documents();
+        Ok(())
+    }
+
+    /// Removes a component's schema. Returns `false` if it wasn't registered.
+    pub fn unregister(&mut self, id: &str) -> Result<bool, String> {
+        if !self.schemas.contains_key(id) {
+            return Ok(false);
+        }
+        let mut schemas = self.schemas.clone();
+        schemas.remove(id);
+        self.scope = compile_scope(&schemas)?;
+        self.schemas = schemas;
+        self.index_documents();
+        Ok(true)
+    }
+
+    /// Compiles a whole set of schemas at once, such as a restored snapshot.
+    pub fn from_schemas(schemas: HashMap<String, Value>) -> Result<Self, String> {
+        let mut registry = ComponentRegistry {
+            scope: compile_scope(&schemas)?,
+            schemas,
+            documents: HashMap::new(),
+        };
+        registry.index_documents();
+        Ok(registry)
+    }
+
+    /// Every registered component and its schema.
+    pub fn schemas(&self) -> &HashMap<String, Value> {
+        &self.schemas
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.schemas.contains_key(id)
+    }
+
+    /// The schema a component was registered with.
+    pub fn get(&self, id: &str) -> Option<&Value> {
+        self.schemas.get(id)
+    }
+
+    /// The component and every component whose schema references it through
+    /// `$ref`, directly or through other components.
+    pub fn dependents(&self, id: &str) -> BTreeSet<String> {
+        let mut dependents = BTreeSet::from([id.to_string()]);
+        let mut pending = vec![id.to_string()];
+        while let Some(target) = pending.pop() {
+            for (other, schema) in &self.schemas {
+                if !dependents.contains(other)
+                    && self.references(schema, &Self::component_url(other), true, &target)
+                {
+                    dependents.insert(other.clone());
+                    pending.push(other.clone());
+                }
+            }
+        }
+        dependents
+    }
+
+    /// A hash of the component's schema and of every schema it references
+    /// through `$ref`, directly or through other components, so it changes
+    /// whenever anything its validation depends on does.
+    pub fn version(&self, id: &str) -> Option<u64> {
+        self.schemas.get(id)?;
+        let mut referenced = BTreeSet::from([id.to_string()]);
+        let mut pending = vec![id.to_string()];
+        while let Some(source) = pending.pop() {
+            let schema = &self.schemas[&source];
+            let base = Self::component_url(&source);
+            for other in self.schemas.keys() {
+                if !referenced.contains(other) && self.references(schema, &base, true, other) {
+                    referenced.insert(other.clone());
+                    pending.push(other.clone());
+                }
+            }
+        }
+        let schemas: BTreeMap<&String, &Value> = referenced
+            .iter()
+            .map(|id| (id, &self.schemas[id]))
+            .collect();
+        Some(hash::of(&schemas))
+    }
+
+    /// Whether any `$ref` in `schema` points into component `target`. `root`
+    /// marks a component's whole schema; see [`inner_base`].
+    fn references(&self, schema: &Value, base: &Url, root: bool, target: &str) -> bool {
+        match schema {
+            Value::Object(object) => {
+                let points_at_target = object
+                    .get("$ref")
+                    .and_then(Value::as_str)
+                    .and_then(|reference| base.join(reference).ok())
+                    .and_then(|url| self.documents.get(&document_key(&url)))
+                    .is_some_and(|(id, _)| id == target);
+                let inner = inner_base(base, schema, root);
+                points_at_target
+                    || object
+                        .values()
+                        .any(|value| self.references(value, &inner, false, target))
+            }
+            Value::Array(items) => items
+                .iter()
+                .any(|item| self.references(item, base, false, target)),
+            _ => false,
+        }
+    }
+
+    fn index_documents(&mut self) {
+        self.documents.clear();
+        for (id, schema) in &self.schemas {
+            let url = Self::component_url(id);
+            let mut nested = Vec::new();
+            nested_documents(schema, &url, "", &mut nested);
+            self.documents
+                .insert(document_key(&url), (id.clone(), String::new()));
+            for (url, pointer) in nested {
+                self.documents
+                    .insert(document_key(&url), (id.clone(), pointer));
+            }
+        }
+    }
+
+    /// Validates `value` against a component's compiled schema, or returns
+    /// `None` if the component isn't registered.
+    pub fn validate(&self, id: &str, value: &Value) -> Option<ValidationState> {
+        let schema = self.scope.resolve(&Self::component_url(id))?;
+        Some(schema.validate(value))
+    }
+
+    /// Returns whether a component's schema declares the value at `path`.
+    ///
+    /// `$ref`s are followed and `allOf`, `anyOf` and `oneOf` branches are
+    /// merged, so a path is declared if any part of the composite schema
+    /// declares it. Properties are looked up in `properties` and then
+    /// `additionalProperties`, sequence elements in `items`, and constant
+    /// indices in `prefixItems` (or a tuple-form `items`) before falling back
+    /// to `items`. Iterating an object yields its keys, which are any string,
+    /// and its values are looked up in `additionalProperties` and
+    /// `patternProperties`. An unconstrained subschema, such as `{}` or
+    /// `additionalProperties: true`, admits any path below it.
+    pub fn declares_path(&self, id: &str, path: &[PathSegment]) -> bool {
+        let Some(schema) = self.schemas.get(id) else {
+            return false;
+        };
+        self.declares(schema, &Self::component_url(id), true, path, 0)
+    }
+
+    /// `base` is the URL `schema`'s own `$ref` resolves against, and `root`
+    /// marks a component's whole schema; see [`inner_base`].
+    fn declares(
+        &self,
+        schema: &Value,
+        base: &Url,
+        root: bool,
+        path: &[PathSegment],
+        depth: usize,
+    ) -> bool {
+        let Some((segment, rest)) = path.split_first() else {
+            return true;
+        };
+        if is_unconstrained(schema) {
+            return true;
+        }
+        if depth > MAX_RESOLUTION_DEPTH {
+            return false;
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            if let Some((target, target_base, target_root)) = self.resolve_ref(base, reference) {
+                if self.declares(target, &target_base, target_root, path, depth + 1) {
+                    return true;
+                }
+            }
+        }
+
+        let inner = inner_base(base, schema, root);
+        let branches = COMPOSITION_KEYWORDS
+            .iter()
+            .filter_map(|keyword| schema.get(*keyword).and_then(Value::as_array))
+            .flatten();
+        for branch in branches {
+            if self.declares(branch, &inner, false, path, depth + 1) {
+                return true;
+            }
+        }
+
+        if segment == &PathSegment::Element && is_object(schema) {
+            // A loop over an object binds its keys, which are strings.
+            if rest.is_empty() {
+                return true;
+            }
+            let patterns = schema
+                .get("patternProperties")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flat_map(|patterns| patterns.values());
+            for value in patterns {
+                if self.declares(value, &inner, false, rest, depth + 1) {
+                    return true;
+                }
+            }
+        }
+
+        step(schema, segment)
+            .is_some_and(|next| self.declares(next, &inner, false, rest, depth + 1))
+    }
+
+    /// Resolves `reference` against `base` to the raw subschema it names,
+    /// the URL that subschema's own `$ref` resolves against, and whether it is
+    /// a component's whole schema.
+    ///
+    /// valico decides whether the reference resolves, so this agrees with
+    /// [`ComponentRegistry::validate`] about which `$ref`s point anywhere.
+    /// The keywords of the target still come from the raw schema: valico's
+    /// [`json_schema::Schema`] keeps its source private and exposes only
+    /// compiled validators, which can't be walked for `properties` or `items`.
+    fn resolve_ref(&self, base: &Url, reference: &str) -> Option<(&Value, Url, bool)> {
+        let url = base.join(reference).ok()?;
+        self.scope.resolve(&url)?;
+        let (id, document) = self.documents.get(&document_key(&url))?;
+        let pointer = format!("{document}{}", url.fragment().unwrap_or(""));
+
+        // Walk down from the component's root to track the base URL.
+        let mut schema = &self.schemas[id];
+        let mut base = Self::component_url(id);
+        let mut root = true;
+        for token in pointer.split('/').skip(1) {
+            base = inner_base(&base, schema, root);
+            schema = schema.pointer(&format!("/{token}"))?;
+            root = false;
+        }
+        Some((schema, base, root))
+    }
+}
+
+/// Compiles every schema into a fresh scope under its component URL.
+fn compile_scope(schemas: &HashMap<String, Value>) -> Result<json_schema::Scope, String> {
+    let mut scope = json_schema::Scope::new();
+    for (id, schema) in schemas {
+        scope
+            .compile_with_id(&ComponentRegistry::component_url(id), schema.clone(), false)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(scope)
+}
+
+/// Collects the URL of every subschema below `schema` that declares its own
+/// `$id`, with the JSON pointer to it. `base` is the URL `schema`'s
+/// subschemas resolve against.
+fn nested_documents(schema: &Value, base: &Url, pointer: &str, found: &mut Vec<(Url, String)>) {
+    let children: Vec<(String, &Value)> = match schema {
+        Value::Object(object) => object
+            .iter()
+            .map(|(key, child)| (key.replace('~', "~0").replace('/', "~1"), child))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(index, child)| (index.to_string(), child))
+            .collect(),
+        _ => return,
+    };
+    for (token, child) in children {
+        let pointer = format!("{pointer}/{token}");
+        let child_base = inner_base(base, child, false);
+        if &child_base != base {
+            found.push((child_base.clone(), pointer.clone()));
+        }
+        nested_documents(child, &child_base, &pointer, found);
+    }
+}
+
+/// The URL the subschemas of `schema` resolve against, given the `base` its
+/// own `$ref` resolves against.
+///
+/// This follows valico rather than the spec: a subschema's `$id` applies to
+/// the subschemas below it but not to its own `$ref`, and a component's root
+/// `$id` is ignored because the root is compiled under the component URL.
+fn inner_base(base: &Url, schema: &Value, root: bool) -> Url {
+    if root {
+        return base.clone();
+    }
+    schema
+        .get("$id")
+        .and_then(Value::as_str)
+        .and_then(|id| base.join(id).ok())
+        .unwrap_or_else(|| base.clone())
+}
+
+/// Looks up the subschema for one path segment, ignoring composition.
+fn step<'a>(schema: &'a Value, segment: &PathSegment) -> Option<&'a Value> {
+    match segment {
+        PathSegment::Property(name) => schema
+            .get("properties")
+            .and_then(|properties| properties.get(name))
+            .or_else(|| object_schema(schema.get("additionalProperties"))),
+        PathSegment::Index(index) => schema
+            .get("prefixItems")
+            .or_else(|| schema.get("items").filter(|items| items.is_array()))
+            .and_then(|items| items.get(*index))
+            .or_else(|| element_schema(schema)),
+        PathSegment::Element => {
+            element_schema(schema).or_else(|| object_schema(schema.get("additionalProperties")))
+        }
+    }
+}
+
+/// The schema every element of an array shares, if it declares one.
+fn element_schema(schema: &Value) -> Option<&Value> {
+    match schema.get("items") {
+        Some(items) if !items.is_array() => object_schema(Some(items)),
+        // Draft 7 style tuple validation puts the shared schema in
+        // `additionalItems`.
+        Some(_) => object_schema(schema.get("additionalItems")),
+        None => None,
+    }
+}
+
+/// Whether a schema admits any value: `true`, or an object with nothing but
+/// annotations.
+fn is_unconstrained(schema: &Value) -> bool {
+    match schema {
+        Value::Bool(allowed) => *allowed,
+        Value::Object(object) => object
+            .keys()
+            .all(|keyword| ANNOTATION_KEYWORDS.contains(&keyword.as_str())),
+        _ => false,
+    }
+}
+
+/// Whether a schema describes an object, by its `type` or by declaring
+/// properties.
+fn is_object(schema: &Value) -> bool {
+    let typed = match schema.get("type") {
+        Some(Value::String(ty)) => ty == "object",
+        Some(Value::Array(types)) => types.iter().any(|ty| ty == "object"),
+        _ => false,
+    };
+    typed
+        || ["properties", "additionalProperties", "patternProperties"]
+            .iter()
+            .any(|keyword| schema.get(*keyword).is_some())
+}
+
+/// Treats `false` as "nothing is allowed" rather than as a schema to descend
+/// into.
+fn object_schema(schema: Option<&Value>) -> Option<&Value> {
+    schema.filter(|schema| schema != &&Value::Bool(false))
+}
+
+/// The key a schema document is stored under: its URL without the fragment.
+fn document_key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.to_string()
+}
@@ -0,0 +1,98 @@
+//! The binary snapshot format for a renderer's compiled state.
+//!
+//! A snapshot is the magic bytes `LEAF`, a little-endian `u32` format version,
+//! a little-endian `u64` hash and a JSON payload. The hash covers the crate
+//! version and the payload, so a snapshot taken by another build of the crate,
+//! or corrupted since it was taken, is re-validated instead of trusted.
+//!
+//! The hash is an unkeyed FNV-1a, not a signature: anyone who alters a
+//! snapshot can recompute it, so a matching hash doesn't mean the snapshot
+//! wasn't tampered with.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::analysis::VariablePath;
+use crate::hash;
+use crate::renderer::{TemplateBuild, TemplateSource};
+
+const MAGIC: &[u8; 4] = b"LEAF";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 4 + 8;
+
+/// Everything needed to bring a renderer back without recompiling it.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    pub schemas: BTreeMap<String, Value>,
+    /// Sorted by name.
+    pub templates: Vec<TemplateSource>,
+    pub dependencies: BTreeMap<String, Vec<String>>,
+    pub variables: BTreeMap<String, BTreeSet<VariablePath>>,
+    #[serde(default)]
+    pub builds: BTreeMap<String, TemplateBuild>,
+}
+
+/// A decoded snapshot, and whether its hash matched its contents.
+#[derive(Debug)]
+pub struct Decoded {
+    pub state: State,
+    pub trusted: bool,
+}
+
+pub fn encode(state: &State) -> Vec<u8> {
+    let payload = serde_json::to_vec(state).unwrap();
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&hash(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Decoded, String> {
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return Err("Not a renderer snapshot".to_string());
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if version != VERSION {
+        return Err(format!("Unsupported snapshot version {version}"));
+    }
+    let expected = u64::from_le_bytes(bytes[8..HEADER_LEN].try_into().unwrap());
+    let payload = &bytes[HEADER_LEN..];
+    let state =
+        serde_json::from_slice(payload).map_err(|e| format!("Failed to parse snapshot: {}", e))?;
+    Ok(Decoded {
+        state,
+        trusted: hash(payload) == expected,
+    })
+}
+
+/// Hashes the crate version along with the payload.
+fn hash(payload: &[u8]) -> u64 {
+    hash::fnv1a([env!("CARGO_PKG_VERSION").as_bytes(), &[0], payload])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_rejects_bad_headers() {
+        let bytes = encode(&State::default());
+        let decoded = decode(&bytes).unwrap();
+        assert!(decoded.trusted);
+        assert!(decoded.state.templates.is_empty());
+
+        assert!(decode(b"LEAF").is_err());
+        assert!(decode(&[&b"NOPE"[..], &bytes[4..]].concat()).is_err());
+        let mut future = bytes.clone();
+        future[4] = 2;
+        assert!(decode(&future).unwrap_err().contains("version 2"));
+
+        let mut tampered = bytes;
+        tampered[8] ^= 1;
+        assert!(!decode(&tampered).unwrap().trusted);
+    }
+}
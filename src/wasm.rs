@@ -0,0 +1,2785 @@
+//! The `extern "C"` exports the JavaScript host calls, as a thin layer over
+//! [`Renderer`]. Inputs arrive as JSON in linear memory and every export
+//! returns a length-prefixed JSON result buffer, except [`export_state`],
+//! whose buffer holds a binary snapshot when it succeeds.
+
+// The host hands us raw pointers into linear memory and is responsible for
+// their validity.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::slice;
+use std::str;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::cache::CacheStats;
+use crate::error::{
+    CompileError, CompileErrorType, RegisterError, RemoveError, RenderError, RenderErrorType,
+    SnapshotError,
+};
+use crate::renderer::{Entity, RegisterRequest, Renderer, RendererConfig, TemplateSource};
+
+#[cfg(target_arch = "wasm32")]
+#[global_allocator]
+static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+
+static RENDERERS: Lazy<Mutex<HashMap<u32, Arc<Mutex<Renderer>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_RENDERER: AtomicU32 = AtomicU32::new(1);
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "console")]
+extern "C" {
+    fn log(ptr: *const u8, len: usize);
+    // fn error(ptr: *const u8, len: usize);
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn js_log(s: &str) {
+    unsafe {
+        log(s.as_ptr(), s.len());
+    }
+}
+
+/// Lets the host time renders and cancel them while they run.
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "leaf")]
+extern "C" {
+    /// Milliseconds on a monotonic clock, like `performance.now()`.
+    fn now() -> f64;
+    /// Returns non-zero to cancel the render in progress on a renderer.
+    fn should_cancel(handle: u32) -> u32;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn host_now() -> f64 {
+    unsafe { now() }
+}
+
+// pub fn js_error(msg: &str) {
+//     unsafe {
+//         error(msg.as_ptr(), msg.len());
+//     }
+// }
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum CompileResult {
+    /// `rebuilt` lists the templates that were compiled. The others were
+    /// unchanged since they were last compiled and were skipped.
+    Success {
+        rebuilt: Vec<String>,
+    },
+    Error {
+        error: CompileError,
+    },
+    /// Returned when at least one template in the batch failed, with the
+    /// outcome of every template keyed by name.
+    Batch {
+        results: BTreeMap<String, TemplateResult>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum TemplateResult {
+    /// The template compiled, but another in the batch failed, so it was not
+    /// committed either.
+    NotCommitted,
+    Error {
+        error: CompileError,
+    },
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum RenderResult {
+    Success { result: String },
+    Error { error: RenderError },
+}
+
+impl From<Result<String, RenderError>> for RenderResult {
+    fn from(result: Result<String, RenderError>) -> Self {
+        match result {
+            Ok(result) => RenderResult::Success { result },
+            Err(error) => RenderResult::Error { error },
+        }
+    }
+}
+
+/// Either one template with a context per render, or a `[template, context]`
+/// pair per render.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum RenderManyRequest {
+    Shared {
+        template: String,
+        contexts: Vec<Value>,
+    },
+    Pairs(Vec<(String, Value)>),
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum RenderManyResult {
+    /// A result per render, in request order.
+    Batch { results: Vec<RenderResult> },
+    /// The request itself couldn't be handled, so nothing was rendered.
+    Error { error: RenderError },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum RegisterResult {
+    /// `broken_templates` lists compiled templates that no longer validate
+    /// against the new schema; they stay compiled.
+    Success {
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        broken_templates: BTreeMap<String, CompileError>,
+    },
+    Error {
+        message: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        broken_templates: BTreeMap<String, CompileError>,
+    },
+}
+
+impl From<Result<BTreeMap<String, CompileError>, RegisterError>> for RegisterResult {
+    fn from(result: Result<BTreeMap<String, CompileError>, RegisterError>) -> Self {
+        match result {
+            Ok(broken_templates) => RegisterResult::Success { broken_templates },
+            Err(RegisterError {
+                message,
+                broken_templates,
+            }) => RegisterResult::Error {
+                message,
+                broken_templates,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum RegisterBatchResult {
+    Batch {
+        results: BTreeMap<String, RegisterResult>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RemoveTemplatesRequest {
+    names: Vec<String>,
+    /// Also remove every template that depends on a removed one, instead of
+    /// refusing the removal.
+    #[serde(default)]
+    cascade: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct UnregisterComponentRequest {
+    id: String,
+    /// Also remove every template that declares the component, instead of
+    /// refusing the removal.
+    #[serde(default)]
+    cascade: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum RemoveResult {
+    /// Lists every template that was removed, including cascaded dependents.
+    Success { removed_templates: Vec<String> },
+    /// Nothing was removed. `dependents` lists the templates that would have
+    /// broken, if that was the reason.
+    Error {
+        message: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        dependents: Vec<String>,
+    },
+}
+
+impl From<Result<Vec<String>, RemoveError>> for RemoveResult {
+    fn from(result: Result<Vec<String>, RemoveError>) -> Self {
+        match result {
+            Ok(removed_templates) => RemoveResult::Success { removed_templates },
+            Err(RemoveError {
+                message,
+                dependents,
+            }) => RemoveResult::Error {
+                message,
+                dependents,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum ConfigureResult {
+    Success,
+    Error { message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum CacheStatsResult {
+    Success {
+        #[serde(flatten)]
+        stats: CacheStats,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// What [`export_state`] returns when it can't take a snapshot. A snapshot
+/// itself is returned as raw bytes, which start with `LEAF` rather than `{`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum ExportResult {
+    Error { message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum ImportResult {
+    /// `revalidated` is set when the snapshot's hash didn't match, so it was
+    /// registered and compiled from scratch.
+    Success { revalidated: bool },
+    Error {
+        message: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        compile_errors: BTreeMap<String, CompileError>,
+    },
+}
+
+impl From<Result<bool, SnapshotError>> for ImportResult {
+    fn from(result: Result<bool, SnapshotError>) -> Self {
+        match result {
+            Ok(revalidated) => ImportResult::Success { revalidated },
+            Err(SnapshotError {
+                message,
+                compile_errors,
+            }) => ImportResult::Error {
+                message,
+                compile_errors,
+            },
+        }
+    }
+}
+
+/// Allocates `len` bytes of linear memory for the host to write inputs into.
+/// The host must release it again with [`dealloc`].
+#[no_mangle]
+pub extern "C" fn alloc(len: usize) -> *mut u8 {
+    let mut buf = Vec::<u8>::with_capacity(len);
+    let ptr = buf.as_mut_ptr();
+    std::mem::forget(buf);
+    ptr
+}
+
+/// Releases memory handed out by [`alloc`] or returned by an export.
+#[no_mangle]
+pub extern "C" fn dealloc(ptr: *mut u8, len: usize) {
+    unsafe {
+        drop(Vec::from_raw_parts(ptr, 0, len));
+    }
+}
+
+/// Size of the little-endian `u32` length prefix on every result buffer.
+const RESULT_HEADER_LEN: usize = 4;
+
+/// Copies `data` into a buffer owned by the caller, prefixed with its length.
+///
+/// Exports return a pointer to this buffer; the host reads the `u32` length,
+/// then that many bytes of JSON, and frees the whole buffer with
+/// `dealloc(ptr, 4 + length)`.
+fn write_result(data: &[u8]) -> *mut u8 {
+    let mut buf = Vec::with_capacity(RESULT_HEADER_LEN + data.len());
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+    let mut buf = std::mem::ManuallyDrop::new(buf.into_boxed_slice());
+    buf.as_mut_ptr()
+}
+
+fn get_renderer(handle: u32) -> Option<Arc<Mutex<Renderer>>> {
+    RENDERERS.lock().unwrap().get(&handle).cloned()
+}
+
+/// Creates an isolated renderer and returns its handle, which every other
+/// export takes as its first argument.
+#[no_mangle]
+pub extern "C" fn create_renderer() -> u32 {
+    let handle = NEXT_RENDERER.fetch_add(1, Ordering::Relaxed);
+    #[allow(unused_mut)]
+    let mut renderer = Renderer::new();
+    #[cfg(target_arch = "wasm32")]
+    renderer.set_cancel_check(move || unsafe { should_cancel(handle) } != 0);
+    RENDERERS
+        .lock()
+        .unwrap()
+        .insert(handle, Arc::new(Mutex::new(renderer)));
+    handle
+}
+
+/// Drops a renderer and everything registered on it. Returns 1 if the handle
+/// was live and 0 otherwise.
+#[no_mangle]
+pub extern "C" fn destroy_renderer(handle: u32) -> u32 {
+    RENDERERS.lock().unwrap().remove(&handle).is_some() as u32
+}
+
+#[no_mangle]
+pub extern "C" fn configure_renderer(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = ConfigureResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let json_bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let result = match serde_json::from_slice::<RendererConfig>(json_bytes) {
+        Ok(config) => {
+            renderer.lock().unwrap().configure(config);
+            ConfigureResult::Success
+        }
+        Err(e) => ConfigureResult::Error {
+            message: format!("Failed to parse renderer config JSON: {}", e),
+        },
+    };
+
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn register_component(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = RegisterResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+            broken_templates: BTreeMap::new(),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let json_bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let result = match serde_json::from_slice::<RegisterRequest>(json_bytes) {
+        Ok(request) => {
+            let mut renderer = renderer.lock().unwrap();
+            let results = renderer.register_components(vec![request.into_parts()]);
+            results.into_values().next().unwrap().into()
+        }
+        Err(_) => RegisterResult::Error {
+            message: "Failed to parse component registration JSON".to_string(),
+            broken_templates: BTreeMap::new(),
+        },
+    };
+
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+/// Registers many component schemas at once. Takes a JSON array of the same
+/// `[component_id, schema, options?]` tuples `register_component` accepts and
+/// returns a result per component.
+#[no_mangle]
+pub extern "C" fn register_components(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = RegisterBatchResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let json_bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let result = match serde_json::from_slice::<Vec<RegisterRequest>>(json_bytes) {
+        Ok(requests) => {
+            let requests = requests
+                .into_iter()
+                .map(RegisterRequest::into_parts)
+                .collect();
+            let results = renderer.lock().unwrap().register_components(requests);
+            RegisterBatchResult::Batch {
+                results: results
+                    .into_iter()
+                    .map(|(name, result)| (name, result.into()))
+                    .collect(),
+            }
+        }
+        Err(e) => RegisterBatchResult::Error {
+            message: format!("Failed to parse component registration JSON: {}", e),
+        },
+    };
+
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+/// Removes compiled templates. Takes `{"names": [...], "cascade": bool}`.
+///
+/// See [`Renderer::remove_templates`].
+#[no_mangle]
+pub extern "C" fn remove_templates(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = RemoveResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+            dependents: Vec::new(),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let json_bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let result = match serde_json::from_slice::<RemoveTemplatesRequest>(json_bytes) {
+        Ok(request) => renderer
+            .lock()
+            .unwrap()
+            .remove_templates(request.names, request.cascade)
+            .into(),
+        Err(e) => RemoveResult::Error {
+            message: format!("Failed to parse template removal JSON: {}", e),
+            dependents: Vec::new(),
+        },
+    };
+
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+/// Removes a registered component. Takes `{"id": ..., "cascade": bool}`.
+///
+/// See [`Renderer::unregister_component`].
+#[no_mangle]
+pub extern "C" fn unregister_component(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = RemoveResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+            dependents: Vec::new(),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let json_bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let result = match serde_json::from_slice::<UnregisterComponentRequest>(json_bytes) {
+        Ok(request) => renderer
+            .lock()
+            .unwrap()
+            .unregister_component(&request.id, request.cascade)
+            .into(),
+        Err(e) => RemoveResult::Error {
+            message: format!("Failed to parse component removal JSON: {}", e),
+            dependents: Vec::new(),
+        },
+    };
+
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn compile_templates(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let error = CompileError {
+            error_type: CompileErrorType::UnknownRenderer,
+            message: format!("Unknown renderer handle {handle}"),
+            missing_dependencies: None,
+        };
+        let result = CompileResult::Error { error };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let json_bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let entities: Vec<Entity> = match serde_json::from_slice(json_bytes) {
+        Ok(t) => t,
+        Err(e) => {
+            let error = CompileError {
+                error_type: CompileErrorType::ParseError,
+                message: e.to_string(),
+                missing_dependencies: None,
+            };
+            let result = CompileResult::Error { error };
+            let result_json = serde_json::to_string(&result).unwrap();
+            return write_result(result_json.as_bytes());
+        }
+    };
+
+    let names: Vec<String> = entities
+        .iter()
+        .filter_map(TemplateSource::from_entity)
+        .map(|template| match template {
+            Ok(template) => template.name,
+            Err((name, _)) => name,
+        })
+        .collect();
+    let result = match renderer.lock().unwrap().compile_entities(&entities) {
+        Ok(rebuilt) => CompileResult::Success { rebuilt },
+        Err(mut errors) => CompileResult::Batch {
+            results: names
+                .into_iter()
+                .map(|name| {
+                    let result = match errors.remove(&name) {
+                        Some(error) => TemplateResult::Error { error },
+                        None => TemplateResult::NotCommitted,
+                    };
+                    (name, result)
+                })
+                .collect(),
+        },
+    };
+
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn render_template(
+    handle: u32,
+    name_ptr: *const u8,
+    name_len: usize,
+    ctx_ptr: *const u8,
+    ctx_len: usize,
+) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = RenderResult::Error {
+            error: RenderError::new(
+                RenderErrorType::UnknownRenderer,
+                &format!("Unknown renderer handle {handle}"),
+            ),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let name_bytes = unsafe { slice::from_raw_parts(name_ptr, name_len) };
+    let name = match str::from_utf8(name_bytes) {
+        Ok(n) => n,
+        Err(_) => {
+            let result = RenderResult::Error {
+                error: RenderError::new(RenderErrorType::ParseError, "Invalid template name"),
+            };
+            let result_json = serde_json::to_string(&result).unwrap();
+            return write_result(result_json.as_bytes());
+        }
+    };
+
+    let ctx_bytes = unsafe { slice::from_raw_parts(ctx_ptr, ctx_len) };
+    let ctx: Value = match serde_json::from_slice(ctx_bytes) {
+        Ok(c) => c,
+        Err(_) => {
+            let result = RenderResult::Error {
+                error: RenderError::new(RenderErrorType::ParseError, "Invalid context"),
+            };
+            let result_json = serde_json::to_string(&result).unwrap();
+            return write_result(result_json.as_bytes());
+        }
+    };
+
+    let result: RenderResult = renderer.lock().unwrap().render(name, ctx).into();
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+/// Renders many contexts in one call. Takes either
+/// `{"template": ..., "contexts": [...]}` or an array of `[template, context]`
+/// pairs. See [`Renderer::render_many`].
+#[no_mangle]
+pub extern "C" fn render_many(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = RenderManyResult::Error {
+            error: RenderError::new(
+                RenderErrorType::UnknownRenderer,
+                &format!("Unknown renderer handle {handle}"),
+            ),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let json_bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let requests = match serde_json::from_slice::<RenderManyRequest>(json_bytes) {
+        Ok(RenderManyRequest::Shared { template, contexts }) => contexts
+            .into_iter()
+            .map(|ctx| (template.clone(), ctx))
+            .collect(),
+        Ok(RenderManyRequest::Pairs(pairs)) => pairs,
+        Err(e) => {
+            let result = RenderManyResult::Error {
+                error: RenderError::new(
+                    RenderErrorType::ParseError,
+                    &format!("Failed to parse render batch JSON: {}", e),
+                ),
+            };
+            let result_json = serde_json::to_string(&result).unwrap();
+            return write_result(result_json.as_bytes());
+        }
+    };
+
+    let results = renderer.lock().unwrap().render_many(requests);
+    let result = RenderManyResult::Batch {
+        results: results.into_iter().map(RenderResult::from).collect(),
+    };
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+/// Renders a template for a Leaf entity. See [`Renderer::render_entity`].
+#[no_mangle]
+pub extern "C" fn render_entity(
+    handle: u32,
+    name_ptr: *const u8,
+    name_len: usize,
+    entity_ptr: *const u8,
+    entity_len: usize,
+) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = RenderResult::Error {
+            error: RenderError::new(
+                RenderErrorType::UnknownRenderer,
+                &format!("Unknown renderer handle {handle}"),
+            ),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let name_bytes = unsafe { slice::from_raw_parts(name_ptr, name_len) };
+    let name = match str::from_utf8(name_bytes) {
+        Ok(n) => n,
+        Err(_) => {
+            let result = RenderResult::Error {
+                error: RenderError::new(RenderErrorType::ParseError, "Invalid template name"),
+            };
+            let result_json = serde_json::to_string(&result).unwrap();
+            return write_result(result_json.as_bytes());
+        }
+    };
+
+    let entity_bytes = unsafe { slice::from_raw_parts(entity_ptr, entity_len) };
+    let entity: Entity = match serde_json::from_slice(entity_bytes) {
+        Ok(e) => e,
+        Err(_) => {
+            let result = RenderResult::Error {
+                error: RenderError::new(RenderErrorType::ParseError, "Invalid entity"),
+            };
+            let result_json = serde_json::to_string(&result).unwrap();
+            return write_result(result_json.as_bytes());
+        }
+    };
+
+    let result: RenderResult = renderer.lock().unwrap().render_entity(name, &entity).into();
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+/// Reports the render cache's hit and miss counts. See
+/// [`Renderer::cache_stats`].
+#[no_mangle]
+pub extern "C" fn cache_stats(handle: u32) -> *mut u8 {
+    let result = match get_renderer(handle) {
+        Some(renderer) => CacheStatsResult::Success {
+            stats: renderer.lock().unwrap().cache_stats(),
+        },
+        None => CacheStatsResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+        },
+    };
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+/// Snapshots the renderer's registry and compiled templates. Unlike the other
+/// exports, the result buffer holds the binary snapshot rather than JSON. An
+/// unknown handle yields a JSON `Error` result instead.
+///
+/// See [`Renderer::export_state`].
+#[no_mangle]
+pub extern "C" fn export_state(handle: u32) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = ExportResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+    let snapshot = renderer.lock().unwrap().export_state();
+    write_result(&snapshot)
+}
+
+/// Restores a snapshot taken by [`export_state`]. See
+/// [`Renderer::import_state`].
+#[no_mangle]
+pub extern "C" fn import_state(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = ImportResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+            compile_errors: BTreeMap::new(),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let result: ImportResult = renderer.lock().unwrap().import_state(bytes).into();
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+/// Restores a snapshot taken by [`export_state`], re-validating it even if its
+/// hash matches. See [`Renderer::import_state_validated`].
+#[no_mangle]
+pub extern "C" fn import_state_validated(handle: u32, ptr: *const u8, len: usize) -> *mut u8 {
+    let Some(renderer) = get_renderer(handle) else {
+        let result = ImportResult::Error {
+            message: format!("Unknown renderer handle {handle}"),
+            compile_errors: BTreeMap::new(),
+        };
+        let result_json = serde_json::to_string(&result).unwrap();
+        return write_result(result_json.as_bytes());
+    };
+
+    let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    let imported = renderer.lock().unwrap().import_state_validated(bytes);
+    let result: ImportResult = imported.map(|()| true).into();
+    let result_json = serde_json::to_string(&result).unwrap();
+    write_result(result_json.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{self, PathSegment, VariablePath};
+    use crate::compose;
+    use crate::renderer::{
+        validate_context, validate_template_variables, ComponentRef, OutputFormat, TEMPLATE_KEY,
+    };
+    use crate::schema::ComponentRegistry;
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+    use std::collections::BTreeSet;
+
+    fn parse_using_serde<T: DeserializeOwned>(ptr: *mut u8) -> Result<T, String> {
+        let len = unsafe { u32::from_le_bytes(*(ptr as *const [u8; RESULT_HEADER_LEN])) } as usize;
+        let bytes = unsafe { slice::from_raw_parts(ptr.add(RESULT_HEADER_LEN), len) };
+        let result_str = String::from_utf8_lossy(bytes).into_owned();
+        dealloc(ptr, RESULT_HEADER_LEN + len);
+        serde_json::from_str(&result_str).map_err(|e| e.to_string())
+    }
+
+    fn extract_vars_from_template(template: &str) -> BTreeSet<VariablePath> {
+        analysis::analyze_template("template", template)
+            .unwrap()
+            .variables
+    }
+
+    fn template_entities(templates: &[TemplateSource]) -> Vec<u8> {
+        let entities: Vec<Entity> = templates
+            .iter()
+            .map(|t| {
+                let mut entity = Entity::new();
+                entity.insert(TEMPLATE_KEY.to_string(), json!(t));
+                entity
+            })
+            .collect();
+        serde_json::to_vec(&entities).unwrap()
+    }
+
+    fn setup_test_templates(handle: u32) -> Vec<u8> {
+        let name_component = (
+            "name_component".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                },
+                "required": ["name"]
+            }),
+        );
+
+        let json = serde_json::to_vec(&name_component).unwrap();
+        parse_using_serde::<RegisterResult>(register_component(handle, json.as_ptr(), json.len()))
+            .unwrap();
+
+        let condition_component = (
+            "condition_component".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "condition": {"type": "boolean"},
+                },
+                "required": ["condition"]
+            }),
+        );
+
+        let json = serde_json::to_vec(&condition_component).unwrap();
+        parse_using_serde::<RegisterResult>(register_component(handle, json.as_ptr(), json.len()))
+            .unwrap();
+
+        let templates = [
+            TemplateSource {
+                name: "test1".to_string(),
+                source: "Hello {{ name }}!".to_string(),
+                components: vec![ComponentRef::Union("name_component".to_string())],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "test2".to_string(),
+                source: "{% if condition %}True{% else %}False{% endif %}".to_string(),
+                components: vec![ComponentRef::Union("condition_component".to_string())],
+                output: OutputFormat::Html,
+            },
+        ];
+        template_entities(&templates)
+    }
+
+    fn setup_template_with_dependency() -> Vec<u8> {
+        template_entities(&[TemplateSource {
+            name: "parent".to_string(),
+            source: "{% include 'child' %}".to_string(),
+            components: vec![],
+            output: OutputFormat::Html,
+        }])
+    }
+
+    #[test]
+    fn test_register_component_success() {
+        let handle = create_renderer();
+        let component = (
+            "test_button".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "label": {"type": "string"},
+                    "url": {"type": "string"}
+                },
+                "required": ["label"]
+            }),
+        );
+        let json = serde_json::to_vec(&component).unwrap();
+
+        parse_using_serde::<RegisterResult>(register_component(handle, json.as_ptr(), json.len()))
+            .unwrap();
+
+        let renderer = get_renderer(handle).unwrap();
+        let renderer = renderer.lock().unwrap();
+        assert!(renderer
+            .components
+            .validate("test_button", &json!({"label": "Go"}))
+            .is_some());
+    }
+
+    #[test]
+    fn test_register_component_invalid_json() {
+        let handle = create_renderer();
+        let bad_json = b"{this is not valid json}";
+        parse_using_serde::<RegisterResult>(register_component(
+            handle,
+            bad_json.as_ptr(),
+            bad_json.len(),
+        ))
+        .unwrap();
+
+        // We don't panic, so just confirm registry unchanged
+        let renderer = get_renderer(handle).unwrap();
+        let renderer = renderer.lock().unwrap();
+        assert!(renderer
+            .components
+            .validate("this is not valid json", &json!({}))
+            .is_none());
+    }
+
+    #[test]
+    fn test_validate_template_variables_authorised() {
+        let handle = create_renderer();
+        let component = (
+            "test_button".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "label": {"type": "string"},
+                    "url": {"type": "string"}
+                },
+                "required": ["label"]
+            }),
+        );
+        let json = serde_json::to_vec(&component).unwrap();
+
+        parse_using_serde::<RegisterResult>(register_component(handle, json.as_ptr(), json.len()))
+            .unwrap();
+
+        let template = TemplateSource {
+            name: "button".into(),
+            source: "{{ label }}".into(),
+            components: vec![ComponentRef::Union("test_button".to_string())],
+            output: OutputFormat::Html,
+        };
+        let vars = extract_vars_from_template(&template.source);
+        let renderer = get_renderer(handle).unwrap();
+        let renderer = renderer.lock().unwrap();
+        assert_eq!(
+            validate_template_variables(&renderer.components, &template.components, &vars),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_validate_template_variables_unauthorised() {
+        let template = TemplateSource {
+            name: "button".into(),
+            source: "{{ unauthorised_variable }}".into(),
+            components: vec![],
+            output: OutputFormat::Html,
+        };
+
+        let vars = extract_vars_from_template(&template.source);
+        let err =
+            validate_template_variables(&ComponentRegistry::new(), &template.components, &vars)
+                .unwrap_err();
+        assert!(err.message.contains("unauthorised_variable"));
+    }
+
+    fn list_component() -> ComponentRegistry {
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register(
+                "list_component".to_string(),
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "items": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "label": {"type": "string"},
+                                    "url": {"type": "string"}
+                                }
+                            }
+                        },
+                        "point": {
+                            "type": "array",
+                            "prefixItems": [
+                                {"type": "object", "properties": {"x": {"type": "number"}}}
+                            ]
+                        },
+                        "labels": {
+                            "type": "object",
+                            "additionalProperties": {
+                                "type": "object",
+                                "properties": {"text": {"type": "string"}}
+                            }
+                        },
+                        "extra": {"type": "object", "additionalProperties": true}
+                    }
+                }),
+            )
+            .unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_validate_template_variables_in_loops() {
+        let registry = list_component();
+        let components = vec![ComponentRef::Union("list_component".to_string())];
+
+        let vars = extract_vars_from_template(
+            "{% for item in items %}{{ loop.index }}{{ item.label }}{% endfor %}",
+        );
+        assert_eq!(
+            validate_template_variables(&registry, &components, &vars),
+            Ok(())
+        );
+
+        let vars =
+            extract_vars_from_template("{% for item in items %}{{ item.missing }}{% endfor %}");
+        let err = validate_template_variables(&registry, &components, &vars).unwrap_err();
+        assert!(err.message.contains("items[].missing"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_validate_template_variables_follows_bindings() {
+        let registry = list_component();
+        let components = vec![ComponentRef::Union("list_component".to_string())];
+
+        let vars = extract_vars_from_template(
+            "{% set first = items[0] %}{{ first.label }}\
+             {% with last = items|last %}{{ last.url }}{% endwith %}\
+             {% for item in items|sort(attribute='label') %}{{ item.url }}{% endfor %}",
+        );
+        assert_eq!(
+            validate_template_variables(&registry, &components, &vars),
+            Ok(())
+        );
+
+        let vars =
+            extract_vars_from_template("{% with it = items[1] %}{{ it.missing }}{% endwith %}");
+        let err = validate_template_variables(&registry, &components, &vars).unwrap_err();
+        assert!(err.message.contains("items[1].missing"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_validate_template_variables_prefix_and_additional_properties() {
+        let registry = list_component();
+        let components = vec![ComponentRef::Union("list_component".to_string())];
+
+        let vars = extract_vars_from_template(
+            "{{ point[0].x }}{{ labels.greeting.text }}{{ labels[key].text }}{{ extra.any.thing }}",
+        );
+        // `key` is read from the context too, so it must be declared.
+        let err = validate_template_variables(&registry, &components, &vars).unwrap_err();
+        assert!(err.message.contains("'key'"), "{}", err.message);
+
+        let vars = extract_vars_from_template(
+            "{{ point[0].x }}{{ labels.greeting.text }}{{ extra.any.thing }}",
+        );
+        assert_eq!(
+            validate_template_variables(&registry, &components, &vars),
+            Ok(())
+        );
+
+        let vars = extract_vars_from_template("{{ labels.greeting.missing }}");
+        assert!(validate_template_variables(&registry, &components, &vars).is_err());
+    }
+
+    #[test]
+    fn test_validate_template_variables_iterates_objects() {
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register(
+                "map_component".to_string(),
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "m": {"type": "object", "additionalProperties": {"type": "string"}},
+                        "p": {
+                            "type": "object",
+                            "patternProperties": {
+                                "^x-": {"type": "object", "properties": {"v": {"type": "string"}}}
+                            }
+                        },
+                        "fixed": {
+                            "type": "object",
+                            "properties": {"a": {"type": "string"}},
+                            "additionalProperties": false
+                        },
+                        "items": {
+                            "type": "array",
+                            "items": {"type": "object", "properties": {"a": {}}}
+                        }
+                    }
+                }),
+            )
+            .unwrap();
+        let components = vec![ComponentRef::Union("map_component".to_string())];
+
+        for source in [
+            "{% for k in m %}{{ k }}{% endfor %}",
+            "{% for k in m %}{{ m[k] }}{% endfor %}",
+            "{% for k in p %}{{ p[k].v }}{% endfor %}",
+            "{% for k in fixed %}{{ k }}{% endfor %}",
+            "{% for i in items %}{% for j in i.a %}{{ j }}{% endfor %}{% endfor %}",
+            "{% for i in items %}{{ i.a.any.thing }}{% endfor %}",
+        ] {
+            let vars = extract_vars_from_template(source);
+            assert_eq!(
+                validate_template_variables(&registry, &components, &vars),
+                Ok(()),
+                "{source}"
+            );
+        }
+
+        for (source, path) in [
+            (
+                "{% for k in p %}{{ p[k].missing }}{% endfor %}",
+                "p[].missing",
+            ),
+            (
+                "{% for k in fixed %}{{ fixed[k].a }}{% endfor %}",
+                "fixed[].a",
+            ),
+        ] {
+            let vars = extract_vars_from_template(source);
+            let err = validate_template_variables(&registry, &components, &vars).unwrap_err();
+            assert!(err.message.contains(path), "{}", err.message);
+        }
+    }
+
+    #[test]
+    fn test_validate_template_variables_resolves_refs_and_composition() {
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register(
+                "link".to_string(),
+                json!({
+                    "type": "object",
+                    "properties": {"href": {"type": "string"}}
+                }),
+            )
+            .unwrap();
+        registry
+            .register(
+                "card".to_string(),
+                json!({
+                    "$defs": {
+                        "image": {
+                            "type": "object",
+                            "properties": {"src": {"type": "string"}}
+                        }
+                    },
+                    "allOf": [
+                        {"$ref": "link"},
+                        {
+                            "type": "object",
+                            "properties": {
+                                "images": {"type": "array", "items": {"$ref": "#/$defs/image"}}
+                            }
+                        }
+                    ],
+                    "anyOf": [
+                        {"properties": {"title": {"type": "string"}}},
+                        {"properties": {"subtitle": {"type": "string"}}}
+                    ]
+                }),
+            )
+            .unwrap();
+        let components = vec![ComponentRef::Union("card".to_string())];
+
+        let vars = extract_vars_from_template(
+            "<a href=\"{{ href }}\">{{ title }}{{ subtitle }}</a>\
+             {% for image in images %}<img src=\"{{ image.src }}\">{% endfor %}",
+        );
+        assert_eq!(
+            validate_template_variables(&registry, &components, &vars),
+            Ok(())
+        );
+
+        let vars =
+            extract_vars_from_template("{% for image in images %}{{ image.alt }}{% endfor %}");
+        let err = validate_template_variables(&registry, &components, &vars).unwrap_err();
+        assert!(err.message.contains("images[].alt"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_declares_path_follows_the_refs_valico_resolves() {
+        let target = json!({"type": "object", "properties": {"href": {"type": "string"}}});
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register("link".to_string(), target.clone())
+            .unwrap();
+        registry
+            .register(
+                "external".to_string(),
+                json!({
+                    "$id": "https://example.com/external",
+                    "$defs": {
+                        "link": target,
+                        "nested": {"$id": "https://example.com/nested", "$ref": "link"}
+                    }
+                }),
+            )
+            .unwrap();
+
+        let path = [
+            PathSegment::Property("link".to_string()),
+            PathSegment::Property("href".to_string()),
+        ];
+        let references = [
+            ("link", true),
+            ("leaf://component/link", true),
+            ("#/$defs/link", true),
+            ("https://example.com/nested", true),
+            // valico compiles a component under its component URL, not the
+            // `$id` at its root.
+            ("https://example.com/external#/$defs/link", false),
+            ("#/$defs/missing", false),
+            ("missing", false),
+            ("https://example.com/missing", false),
+        ];
+        for (reference, resolves) in references {
+            registry
+                .register(
+                    "card".to_string(),
+                    json!({
+                        "$id": "https://example.com/card",
+                        "$defs": {"link": target},
+                        "properties": {"link": {"$ref": reference}}
+                    }),
+                )
+                .unwrap();
+            let state = registry
+                .validate("card", &json!({"link": {"href": 1}}))
+                .unwrap();
+
+            // valico either applies the target, rejecting the number, or
+            // reports the reference as missing.
+            assert_eq!(!state.is_valid(), resolves, "{reference}");
+            assert_eq!(state.missing.is_empty(), resolves, "{reference}");
+            assert_eq!(
+                registry.declares_path("card", &path),
+                resolves,
+                "{reference}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_register_component_replaces_schema() {
+        let mut registry = ComponentRegistry::new();
+        let vars = extract_vars_from_template("{{ label }}");
+        let components = vec![ComponentRef::Union("button".to_string())];
+
+        registry
+            .register("button".to_string(), json!({"properties": {"text": {}}}))
+            .unwrap();
+        assert!(validate_template_variables(&registry, &components, &vars).is_err());
+
+        registry
+            .register("button".to_string(), json!({"properties": {"label": {}}}))
+            .unwrap();
+        assert_eq!(
+            validate_template_variables(&registry, &components, &vars),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_validate_template_variables_ignores_locals_and_globals() {
+        let vars = extract_vars_from_template(
+            "{% for i in range(3) %}{{ i }}{% endfor %}\
+             {% set n = namespace(count=0) %}{{ n.count }}\
+             {% macro greet(who) %}{{ who.name }}{% endmacro %}{{ greet('x') }}",
+        );
+        assert_eq!(
+            validate_template_variables(&ComponentRegistry::new(), &[], &vars),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_compile_templates() {
+        let handle = create_renderer();
+        let templates = setup_test_templates(handle);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(matches!(result, CompileResult::Success { .. }));
+    }
+
+    #[test]
+    fn test_compile_invalid_templates() {
+        let handle = create_renderer();
+        let invalid_json = b"invalid json";
+        let result = compile_templates(handle, invalid_json.as_ptr(), invalid_json.len());
+
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        match result {
+            CompileResult::Error { error } => {
+                assert_eq!(error.error_type, CompileErrorType::ParseError);
+                assert!(error.message.contains("expected value"));
+                assert!(error.missing_dependencies.is_none());
+            }
+            _ => panic!("Expected error result"),
+        }
+    }
+
+    #[test]
+    fn test_compile_templates_collects_every_error() {
+        let handle = create_renderer();
+        let templates = template_entities(&[
+            TemplateSource {
+                name: "batch_syntax".to_string(),
+                source: "{{ invalid syntax }}".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "batch_schema".to_string(),
+                source: "{{ not_in_schema }}".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "batch_ok".to_string(),
+                source: "Static".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+        ]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        let CompileResult::Batch { results } = result else {
+            panic!("Expected batch result, got {:?}", result);
+        };
+        assert_eq!(results.len(), 3);
+        match &results["batch_syntax"] {
+            TemplateResult::Error { error } => {
+                assert_eq!(error.error_type, CompileErrorType::ParseError)
+            }
+            r => panic!("Expected parse error, got {:?}", r),
+        }
+        match &results["batch_schema"] {
+            TemplateResult::Error { error } => {
+                assert_eq!(error.error_type, CompileErrorType::SchemaValidationError);
+                assert!(error.message.contains("not_in_schema"));
+            }
+            r => panic!("Expected schema error, got {:?}", r),
+        }
+        assert!(matches!(results["batch_ok"], TemplateResult::NotCommitted));
+    }
+
+    #[test]
+    fn test_unknown_output_format_fails_batch() {
+        let handle = create_renderer();
+        let template = |name: &str, output: &str| {
+            json!({TEMPLATE_KEY: {
+                "name": name,
+                "source": "Hi",
+                "components": [],
+                "output": output,
+            }})
+        };
+        let request = json!([template("shouty", "HTML"), template("plain", "text")]);
+        let result = call_with_json(compile_templates, handle, request);
+        let CompileResult::Batch { results } = parse_using_serde(result).unwrap() else {
+            panic!("Expected a batch result");
+        };
+        match &results["shouty"] {
+            TemplateResult::Error { error } => {
+                assert_eq!(error.error_type, CompileErrorType::ParseError);
+                assert!(error.message.contains("output"), "{}", error.message);
+            }
+            r => panic!("Expected parse error, got {:?}", r),
+        }
+        assert!(matches!(results["plain"], TemplateResult::NotCommitted));
+        let renderer = get_renderer(handle).unwrap();
+        assert!(renderer.lock().unwrap().env.get_template("plain").is_err());
+    }
+
+    #[test]
+    fn test_malformed_component_ref_fails_batch() {
+        let handle = create_renderer();
+        let template = |name: &str, component: Value| {
+            json!({TEMPLATE_KEY: {
+                "name": name,
+                "source": "Hi",
+                "components": [component],
+            }})
+        };
+        let request = json!([
+            template("numbered", json!({"id": 1})),
+            template("misspelled", json!({"id": "card", "alias": "card"})),
+            template("plain", json!("card")),
+        ]);
+        let result = call_with_json(compile_templates, handle, request);
+        let CompileResult::Batch { results } = parse_using_serde(result).unwrap() else {
+            panic!("Expected a batch result");
+        };
+        for name in ["numbered", "misspelled"] {
+            match &results[name] {
+                TemplateResult::Error { error } => {
+                    assert_eq!(error.error_type, CompileErrorType::ParseError);
+                    assert!(error.message.contains("component"), "{}", error.message);
+                }
+                r => panic!("Expected parse error for {name}, got {:?}", r),
+            }
+        }
+        assert!(matches!(results["plain"], TemplateResult::NotCommitted));
+    }
+
+    #[test]
+    fn test_failed_batch_leaves_environment_unchanged() {
+        let handle = create_renderer();
+        let templates = template_entities(&[TemplateSource {
+            name: "tx_live".to_string(),
+            source: "Live".to_string(),
+            components: vec![],
+            output: OutputFormat::Html,
+        }]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(matches!(result, CompileResult::Success { .. }));
+
+        let templates = template_entities(&[
+            TemplateSource {
+                name: "tx_live".to_string(),
+                source: "Edited".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "tx_new".to_string(),
+                source: "New".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "tx_broken".to_string(),
+                source: "{% if %}".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+        ]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        let CompileResult::Batch { results } = result else {
+            panic!("Expected batch result, got {:?}", result);
+        };
+        assert!(matches!(results["tx_new"], TemplateResult::NotCommitted));
+        assert!(matches!(results["tx_live"], TemplateResult::NotCommitted));
+
+        let renderer = get_renderer(handle).unwrap();
+        let env = &renderer.lock().unwrap().env;
+        assert_eq!(
+            env.get_template("tx_live").unwrap().render(()).unwrap(),
+            "Live"
+        );
+        assert!(env.get_template("tx_new").is_err());
+        assert!(env.get_template("tx_broken").is_err());
+    }
+
+    #[test]
+    fn test_compile_template_with_missing_dependency() {
+        let handle = create_renderer();
+        let templates = setup_template_with_dependency();
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        let CompileResult::Batch { results } = result else {
+            panic!("Expected batch result, got {:?}", result);
+        };
+        match &results["parent"] {
+            TemplateResult::Error { error } => {
+                assert_eq!(error.error_type, CompileErrorType::MissingDependency);
+                assert_eq!(error.missing_dependencies, Some(vec!["child".to_string()]));
+            }
+            r => panic!("Expected missing dependency, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_compile_templates_in_dependency_order() {
+        let handle = create_renderer();
+        let templates = template_entities(&[
+            TemplateSource {
+                name: "deps_page".to_string(),
+                source: "{% extends 'deps_layout' %}{% block body %}{% from 'deps_macros' import shout %}{{ shout('hi') }}{% endblock %}".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "deps_layout".to_string(),
+                source: "<main>{% block body %}{% endblock %}</main>".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "deps_macros".to_string(),
+                source: "{% macro shout(s) %}{{ s|upper }}{% endmacro %}".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+        ]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+
+        let renderer = get_renderer(handle).unwrap();
+        let renderer = renderer.lock().unwrap();
+        assert_eq!(
+            renderer.template_dependencies["deps_page"],
+            vec!["deps_layout".to_string(), "deps_macros".to_string()]
+        );
+        let rendered = renderer
+            .env
+            .get_template("deps_page")
+            .unwrap()
+            .render(())
+            .unwrap();
+        assert_eq!(rendered, "<main>HI</main>");
+    }
+
+    #[test]
+    fn test_render_template() {
+        let handle = create_renderer();
+        // First compile the template
+        let templates = setup_test_templates(handle);
+
+        let compile_result = compile_templates(handle, templates.as_ptr(), templates.len());
+
+        let compile_result: CompileResult = parse_using_serde(compile_result).unwrap();
+        match compile_result {
+            CompileResult::Success { .. } => {
+                println!("compile_result: {:#?}", compile_result);
+            }
+            CompileResult::Error { error } => {
+                println!("compile_result: {:#?}", error);
+            }
+            CompileResult::Batch { results } => {
+                println!("compile_result: {:#?}", results);
+            }
+        }
+
+        // Test rendering template1
+        let name = "test1";
+        let context = json!({"name": "World"});
+        let context_bytes = serde_json::to_vec(&context).unwrap();
+        let result = render_template(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            context_bytes.as_ptr(),
+            context_bytes.len(),
+        );
+
+        let result: RenderResult = parse_using_serde(result).unwrap();
+
+        match result {
+            RenderResult::Success { result } => {
+                assert_eq!(result, "Hello World!");
+            }
+            RenderResult::Error { error } => {
+                panic!("Expected success result, got error: {}", error.message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_template_with_condition() {
+        let handle = create_renderer();
+        // First compile the template
+        let templates = setup_test_templates(handle);
+
+        parse_using_serde::<CompileResult>(compile_templates(
+            handle,
+            templates.as_ptr(),
+            templates.len(),
+        ))
+        .unwrap();
+
+        // Test rendering template2 with true condition
+        let name = "test2";
+        let context = json!({"condition": true});
+        let context_bytes = serde_json::to_vec(&context).unwrap();
+        let result = render_template(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            context_bytes.as_ptr(),
+            context_bytes.len(),
+        );
+
+        let result: RenderResult = parse_using_serde(result).unwrap();
+        match result {
+            RenderResult::Success { result } => {
+                assert_eq!(result, "True");
+            }
+            RenderResult::Error { error } => {
+                panic!("Expected success result, got error: {}", error.message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_template_with_false_condition() {
+        let handle = create_renderer();
+        // First compile the template
+        let templates = setup_test_templates(handle);
+
+        parse_using_serde::<CompileResult>(compile_templates(
+            handle,
+            templates.as_ptr(),
+            templates.len(),
+        ))
+        .unwrap();
+
+        let context = json!({"condition": false});
+        let context_bytes = serde_json::to_vec(&context).unwrap();
+
+        let name = "test2";
+        let result = render_template(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            context_bytes.as_ptr(),
+            context_bytes.len(),
+        );
+
+        let result: RenderResult = parse_using_serde(result).unwrap();
+        match result {
+            RenderResult::Success { result } => {
+                assert_eq!(result, "False");
+            }
+            RenderResult::Error { error } => {
+                panic!("Expected success result, got error: {}", error.message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_nonexistent_template() {
+        let handle = create_renderer();
+        let name = "nonexistent";
+        let context = json!({});
+        let context_bytes = serde_json::to_vec(&context).unwrap();
+        let result = render_template(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            context_bytes.as_ptr(),
+            context_bytes.len(),
+        );
+
+        let result: RenderResult = parse_using_serde(result).unwrap();
+        match result {
+            RenderResult::Error { error } => {
+                assert_eq!(error.error_type, RenderErrorType::ParseError);
+                assert!(error.message.contains("Template not found"));
+            }
+            RenderResult::Success { result } => {
+                panic!("Expected error result, got success: {}", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_with_invalid_context() {
+        let handle = create_renderer();
+        let name = "test1";
+        let invalid_context = b"invalid json";
+        let result = render_template(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            invalid_context.as_ptr(),
+            invalid_context.len(),
+        );
+
+        println!("Result: {:?}", result);
+
+        let result: RenderResult = parse_using_serde(result).unwrap();
+        match result {
+            RenderResult::Error { error } => {
+                assert_eq!(error.error_type, RenderErrorType::ParseError);
+                assert!(error.message.contains("Invalid context"));
+            }
+            RenderResult::Success { result } => {
+                panic!("Expected error result, got success: {}", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_context_reports_every_violation() {
+        let handle = create_renderer();
+        let component = (
+            "context_card".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "title": {"type": "string"},
+                    "count": {"type": "integer"}
+                },
+                "required": ["title"]
+            }),
+        );
+        let json = serde_json::to_vec(&component).unwrap();
+        parse_using_serde::<RegisterResult>(register_component(handle, json.as_ptr(), json.len()))
+            .unwrap();
+
+        let renderer = get_renderer(handle).unwrap();
+        let registry = &renderer.lock().unwrap().components;
+        let components = vec![ComponentRef::Union("context_card".to_string())];
+        assert_eq!(
+            validate_context(registry, &components, &json!({"title": "Hi", "count": 3})),
+            Ok(())
+        );
+
+        let violations =
+            validate_context(registry, &components, &json!({"count": "three"})).unwrap_err();
+        assert!(violations
+            .iter()
+            .any(|v| v.keyword == "required" && v.pointer == "/title"));
+        assert!(violations
+            .iter()
+            .any(|v| v.keyword == "wrong_type" && v.pointer == "/count"));
+        assert!(violations.iter().all(|v| v.component == "context_card"));
+    }
+
+    #[test]
+    fn test_render_template_with_context_validation() {
+        let handle = create_renderer();
+        let component = (
+            "validated_name".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                },
+                "required": ["name"]
+            }),
+        );
+        let json = serde_json::to_vec(&component).unwrap();
+        parse_using_serde::<RegisterResult>(register_component(handle, json.as_ptr(), json.len()))
+            .unwrap();
+
+        let templates = template_entities(&[TemplateSource {
+            name: "validated".to_string(),
+            source: "Hello {{ name }}!".to_string(),
+            components: vec![ComponentRef::Union("validated_name".to_string())],
+            output: OutputFormat::Html,
+        }]);
+        parse_using_serde::<CompileResult>(compile_templates(
+            handle,
+            templates.as_ptr(),
+            templates.len(),
+        ))
+        .unwrap();
+
+        let config = serde_json::to_vec(&json!({"validate_context": true})).unwrap();
+        let result = configure_renderer(handle, config.as_ptr(), config.len());
+        let result: ConfigureResult = parse_using_serde(result).unwrap();
+        assert!(matches!(result, ConfigureResult::Success));
+
+        let name = "validated";
+        let context_bytes = serde_json::to_vec(&json!({"name": 42})).unwrap();
+        let result = render_template(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            context_bytes.as_ptr(),
+            context_bytes.len(),
+        );
+        let result: RenderResult = parse_using_serde(result).unwrap();
+
+        match result {
+            RenderResult::Error { error } => {
+                assert_eq!(error.error_type, RenderErrorType::ContextValidationError);
+                let violations = error.violations.unwrap();
+                assert_eq!(violations.len(), 1);
+                assert_eq!(violations[0].pointer, "/name");
+                assert_eq!(violations[0].keyword, "wrong_type");
+            }
+            RenderResult::Success { result } => {
+                panic!("Expected error result, got success: {}", result);
+            }
+        }
+    }
+
+    fn register_test_component(handle: u32, id: &str, schema: Value) {
+        let json = serde_json::to_vec(&(id, schema)).unwrap();
+        let result = register_component(handle, json.as_ptr(), json.len());
+        let result: RegisterResult = parse_using_serde(result).unwrap();
+        assert!(matches!(result, RegisterResult::Success { .. }));
+    }
+
+    fn register_link_components(handle: u32) {
+        for id in ["button", "link"] {
+            register_test_component(
+                handle,
+                id,
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "label": {"type": "string"},
+                        "url": {"type": "string"}
+                    },
+                    "required": ["label"]
+                }),
+            );
+        }
+    }
+
+    #[test]
+    fn test_namespaced_components() {
+        let handle = create_renderer();
+        register_link_components(handle);
+
+        let entities = serde_json::to_vec(&json!([{
+            TEMPLATE_KEY: {
+                "name": "namespaced",
+                "source": "{{ components.button.label }} {{ lnk.label }}",
+                "components": [{"id": "button"}, {"id": "link", "as": "lnk"}]
+            }
+        }]))
+        .unwrap();
+        let result = compile_templates(handle, entities.as_ptr(), entities.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+
+        let config = serde_json::to_vec(&json!({"validate_context": true})).unwrap();
+        parse_using_serde::<ConfigureResult>(configure_renderer(
+            handle,
+            config.as_ptr(),
+            config.len(),
+        ))
+        .unwrap();
+
+        let render = |ctx: Value| {
+            let name = "namespaced";
+            let ctx = serde_json::to_vec(&ctx).unwrap();
+            let result =
+                render_template(handle, name.as_ptr(), name.len(), ctx.as_ptr(), ctx.len());
+            parse_using_serde::<RenderResult>(result).unwrap()
+        };
+
+        let result = render(json!({
+            "components": {"button": {"label": "Go"}},
+            "lnk": {"label": "Home", "url": "/"}
+        }));
+        assert!(matches!(result, RenderResult::Success { result } if result == "Go Home"));
+
+        let RenderResult::Error { error } = render(json!({
+            "components": {"button": {"label": 1}}
+        })) else {
+            panic!("Expected context validation to fail");
+        };
+        let violations = error.violations.unwrap();
+        assert!(violations
+            .iter()
+            .any(|v| v.component == "button" && v.pointer == "/components/button/label"));
+        assert!(violations
+            .iter()
+            .any(|v| v.component == "link" && v.pointer == "/lnk" && v.keyword == "required"));
+    }
+
+    #[test]
+    fn test_namespaced_component_with_non_identifier_id() {
+        let handle = create_renderer();
+        let id = "button:01JVK339CW6Q67VAMXCA7XAK7D";
+        register_test_component(
+            handle,
+            id,
+            json!({"type": "object", "properties": {"label": {"type": "string"}}}),
+        );
+        let entities = serde_json::to_vec(&json!([{
+            TEMPLATE_KEY: {
+                "name": "namespaced",
+                "source": format!("{{{{ components[\"{id}\"].label }}}}"),
+                "components": [{"id": id}]
+            }
+        }]))
+        .unwrap();
+        let result = compile_templates(handle, entities.as_ptr(), entities.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+
+        let name = "namespaced";
+        let ctx = serde_json::to_vec(&json!({"components": {id: {"label": "Go"}}})).unwrap();
+        let result = render_template(handle, name.as_ptr(), name.len(), ctx.as_ptr(), ctx.len());
+        let result = parse_using_serde::<RenderResult>(result).unwrap();
+        assert!(matches!(result, RenderResult::Success { result } if result == "Go"));
+
+        // The constant subscript is still checked against the schema.
+        let entities = serde_json::to_vec(&json!([{
+            TEMPLATE_KEY: {
+                "name": "misspelled",
+                "source": format!("{{{{ components[\"{id}\"].lable }}}}"),
+                "components": [{"id": id}]
+            }
+        }]))
+        .unwrap();
+        let result = compile_templates(handle, entities.as_ptr(), entities.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Batch { .. }),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_union_components_report_collisions() {
+        let handle = create_renderer();
+        register_link_components(handle);
+
+        let union = vec![
+            ComponentRef::Union("button".to_string()),
+            ComponentRef::Union("link".to_string()),
+        ];
+        let templates = template_entities(&[
+            TemplateSource {
+                name: "ambiguous".to_string(),
+                source: "{{ label }}".to_string(),
+                components: union.clone(),
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "not_namespaced".to_string(),
+                source: "{{ components.button.label }}".to_string(),
+                components: union,
+                output: OutputFormat::Html,
+            },
+        ]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let CompileResult::Batch { results } = parse_using_serde(result).unwrap() else {
+            panic!("Expected batch result");
+        };
+
+        let TemplateResult::Error { error } = &results["ambiguous"] else {
+            panic!("Expected a collision");
+        };
+        assert_eq!(error.error_type, CompileErrorType::ComponentCollision);
+        assert!(error.message.contains("button, link"), "{}", error.message);
+
+        let TemplateResult::Error { error } = &results["not_namespaced"] else {
+            panic!("Expected union components not to be namespaced");
+        };
+        assert_eq!(error.error_type, CompileErrorType::SchemaValidationError);
+    }
+
+    fn render_test_entity(handle: u32, name: &str, entity: Value) -> RenderResult {
+        let entity = serde_json::to_vec(&entity).unwrap();
+        let result = render_entity(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            entity.as_ptr(),
+            entity.len(),
+        );
+        parse_using_serde(result).unwrap()
+    }
+
+    #[test]
+    fn test_render_entity() {
+        let handle = create_renderer();
+        register_link_components(handle);
+        register_test_component(
+            handle,
+            "title",
+            json!({
+                "type": "object",
+                "properties": {"title": {"type": "string"}},
+                "required": ["title"]
+            }),
+        );
+
+        let entities = serde_json::to_vec(&json!([{
+            TEMPLATE_KEY: {
+                "name": "card",
+                "source": "{{ title }}: <a href=\"{{ link.url }}\">{{ components.button.label }}</a>",
+                "components": ["title", {"id": "link", "as": "link"}, {"id": "button"}]
+            }
+        }]))
+        .unwrap();
+        let result = compile_templates(handle, entities.as_ptr(), entities.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+
+        let result = render_test_entity(
+            handle,
+            "card",
+            json!({
+                "title": {"title": "Docs"},
+                "link": {"label": "Docs", "url": "/docs"},
+                "button": {"label": "Open"},
+                "unrelated": {"ignored": true}
+            }),
+        );
+        match result {
+            RenderResult::Success { result } => {
+                assert_eq!(result, "Docs: <a href=\"&#x2f;docs\">Open</a>")
+            }
+            RenderResult::Error { error } => panic!("Expected success, got {:?}", error),
+        }
+
+        let RenderResult::Error { error } =
+            render_test_entity(handle, "card", json!({"title": {"title": "Docs"}}))
+        else {
+            panic!("Expected missing components to fail");
+        };
+        assert_eq!(error.error_type, RenderErrorType::MissingComponent);
+        assert_eq!(
+            error.missing_components,
+            Some(vec!["link".to_string(), "button".to_string()])
+        );
+
+        let RenderResult::Error { error } = render_test_entity(
+            handle,
+            "card",
+            json!({
+                "title": {"title": 7},
+                "link": {"label": "Docs"},
+                "button": {"label": "Open"}
+            }),
+        ) else {
+            panic!("Expected entity validation to fail");
+        };
+        assert_eq!(error.error_type, RenderErrorType::ContextValidationError);
+        let violations = error.violations.unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].component, "title");
+        assert_eq!(violations[0].pointer, "/title");
+    }
+
+    #[test]
+    fn test_render_nested_components() {
+        let handle = create_renderer();
+        register_test_component(
+            handle,
+            "card",
+            json!({"type": "object", "properties": {"title": {"type": "string"}}}),
+        );
+        register_test_component(
+            handle,
+            "person",
+            json!({"type": "object", "properties": {"name": {"type": "string"}}}),
+        );
+        register_test_component(
+            handle,
+            "page",
+            json!({
+                "type": "object",
+                "properties": {
+                    "items": {"type": "array", "items": {"type": "object"}},
+                    "featured": {"type": "object", "additionalProperties": true}
+                }
+            }),
+        );
+
+        let templates = template_entities(&[
+            TemplateSource {
+                name: "card_view".to_string(),
+                source: "<div>{{ title }}</div>".to_string(),
+                components: vec![ComponentRef::Union("card".to_string())],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "person_card_view".to_string(),
+                source: "<p>{{ name }}: {{ title }}</p>".to_string(),
+                components: vec![
+                    ComponentRef::Union("card".to_string()),
+                    ComponentRef::Union("person".to_string()),
+                ],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "page_view".to_string(),
+                source: "{% for item in items %}{{ render(item) }}{% endfor %}\
+                         {{ component('card', featured) }}"
+                    .to_string(),
+                components: vec![ComponentRef::Union("page".to_string())],
+                output: OutputFormat::Html,
+            },
+        ]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+
+        let name = "page_view";
+        let ctx = serde_json::to_vec(&json!({
+            "items": [
+                {"card": {"title": "A"}},
+                {"card": {"title": "B"}, "person": {"name": "Ann"}}
+            ],
+            "featured": {"title": "F"}
+        }))
+        .unwrap();
+        let result = render_template(handle, name.as_ptr(), name.len(), ctx.as_ptr(), ctx.len());
+        match parse_using_serde(result).unwrap() {
+            RenderResult::Success { result } => {
+                assert_eq!(result, "<div>A</div><p>Ann: B</p><div>F</div>")
+            }
+            RenderResult::Error { error } => panic!("Expected success, got {:?}", error),
+        }
+
+        let ctx = serde_json::to_vec(&json!({
+            "items": [{"unknown": {}}],
+            "featured": {"title": "F"}
+        }))
+        .unwrap();
+        let result = render_template(handle, name.as_ptr(), name.len(), ctx.as_ptr(), ctx.len());
+        let RenderResult::Error { error } = parse_using_serde(result).unwrap() else {
+            panic!("Expected an entity without a template to fail");
+        };
+        assert!(error
+            .message
+            .contains("No template renders components [unknown]"));
+    }
+
+    #[test]
+    fn test_render_nested_components_depth_limit() {
+        let handle = create_renderer();
+        register_test_component(
+            handle,
+            "node",
+            json!({"type": "object", "properties": {"child": {"type": "object"}}}),
+        );
+        let templates = template_entities(&[TemplateSource {
+            name: "node_view".to_string(),
+            source: "[{% if child %}{{ render(child) }}{% endif %}]".to_string(),
+            components: vec![ComponentRef::Union("node".to_string())],
+            output: OutputFormat::Html,
+        }]);
+        parse_using_serde::<CompileResult>(compile_templates(
+            handle,
+            templates.as_ptr(),
+            templates.len(),
+        ))
+        .unwrap();
+
+        let nest = |depth: usize| {
+            let mut entity = json!({"node": {"child": null}});
+            for _ in 0..depth {
+                entity = json!({"node": {"child": entity}});
+            }
+            entity
+        };
+
+        let name = "node_view";
+        let ctx = serde_json::to_vec(&nest(2)["node"]).unwrap();
+        let result = render_template(handle, name.as_ptr(), name.len(), ctx.as_ptr(), ctx.len());
+        assert!(matches!(
+            parse_using_serde(result).unwrap(),
+            RenderResult::Success { result } if result == "[[[]]]"
+        ));
+
+        let ctx = serde_json::to_vec(&nest(compose::MAX_COMPONENT_DEPTH + 1)["node"]).unwrap();
+        let result = render_template(handle, name.as_ptr(), name.len(), ctx.as_ptr(), ctx.len());
+        let RenderResult::Error { error } = parse_using_serde(result).unwrap() else {
+            panic!("Expected deep nesting to fail");
+        };
+        assert!(
+            error
+                .causes
+                .iter()
+                .chain([&error.message])
+                .any(|m| m.contains("depth limit")),
+            "{:?}",
+            error
+        );
+    }
+
+    fn call_with_json(
+        export: extern "C" fn(u32, *const u8, usize) -> *mut u8,
+        handle: u32,
+        request: Value,
+    ) -> *mut u8 {
+        let json = serde_json::to_vec(&request).unwrap();
+        export(handle, json.as_ptr(), json.len())
+    }
+
+    fn compile_removal_fixture(handle: u32) {
+        register_test_component(
+            handle,
+            "title",
+            json!({"type": "object", "properties": {"title": {"type": "string"}}}),
+        );
+        let templates = template_entities(&[
+            TemplateSource {
+                name: "layout".to_string(),
+                source: "<main>{% block body %}{% endblock %}</main>".to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "page".to_string(),
+                source:
+                    "{% extends 'layout' %}{% block body %}{% include 'heading' %}{% endblock %}"
+                        .to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "heading".to_string(),
+                source: "<h1>{{ title }}</h1>".to_string(),
+                components: vec![ComponentRef::Union("title".to_string())],
+                output: OutputFormat::Html,
+            },
+        ]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_remove_templates() {
+        let handle = create_renderer();
+        compile_removal_fixture(handle);
+
+        let result = call_with_json(remove_templates, handle, json!({"names": ["missing"]}));
+        assert!(matches!(
+            parse_using_serde(result).unwrap(),
+            RemoveResult::Error { message, .. } if message.contains("missing")
+        ));
+
+        let result = call_with_json(remove_templates, handle, json!({"names": ["layout"]}));
+        let RemoveResult::Error { dependents, .. } = parse_using_serde(result).unwrap() else {
+            panic!("Expected removal to be refused");
+        };
+        assert_eq!(dependents, vec!["page".to_string()]);
+
+        let renderer = get_renderer(handle).unwrap();
+        assert!(renderer.lock().unwrap().env.get_template("layout").is_ok());
+
+        let result = call_with_json(
+            remove_templates,
+            handle,
+            json!({"names": ["layout"], "cascade": true}),
+        );
+        let RemoveResult::Success { removed_templates } = parse_using_serde(result).unwrap() else {
+            panic!("Expected cascading removal to succeed");
+        };
+        assert_eq!(removed_templates, vec!["layout", "page"]);
+
+        let renderer = renderer.lock().unwrap();
+        assert!(renderer.env.get_template("page").is_err());
+        assert!(renderer.env.get_template("heading").is_ok());
+        assert!(!renderer.template_dependencies.contains_key("page"));
+    }
+
+    #[test]
+    fn test_unregister_component() {
+        let handle = create_renderer();
+        compile_removal_fixture(handle);
+
+        let result = call_with_json(unregister_component, handle, json!({"id": "title"}));
+        let RemoveResult::Error { dependents, .. } = parse_using_serde(result).unwrap() else {
+            panic!("Expected unregistering to be refused");
+        };
+        assert_eq!(dependents, vec!["heading", "page"]);
+
+        let result = call_with_json(
+            unregister_component,
+            handle,
+            json!({"id": "title", "cascade": true}),
+        );
+        let RemoveResult::Success { removed_templates } = parse_using_serde(result).unwrap() else {
+            panic!("Expected cascading unregister to succeed");
+        };
+        assert_eq!(removed_templates, vec!["heading", "page"]);
+
+        let renderer = get_renderer(handle).unwrap();
+        let renderer = renderer.lock().unwrap();
+        assert!(!renderer.components.contains("title"));
+        assert!(renderer.env.get_template("layout").is_ok());
+        assert!(renderer.env.get_template("heading").is_err());
+    }
+
+    #[test]
+    fn test_reregistering_component_revalidates_templates() {
+        let handle = create_renderer();
+        register_test_component(
+            handle,
+            "contact",
+            json!({"type": "object", "properties": {"email": {"type": "string"}}}),
+        );
+        register_test_component(
+            handle,
+            "profile",
+            json!({
+                "allOf": [
+                    {"$ref": "contact"},
+                    {"type": "object", "properties": {"name": {"type": "string"}}}
+                ]
+            }),
+        );
+        let templates = template_entities(&[
+            TemplateSource {
+                name: "profile_view".to_string(),
+                source: "{{ name }} <{{ email }}>".to_string(),
+                components: vec![ComponentRef::Union("profile".to_string())],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "name_view".to_string(),
+                source: "{{ name }}".to_string(),
+                components: vec![ComponentRef::Union("profile".to_string())],
+                output: OutputFormat::Html,
+            },
+        ]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+
+        // `profile` pulls `email` in through a `$ref` to `contact`, so
+        // changing `contact` affects templates that only declare `profile`.
+        let without_email = json!({"type": "object", "properties": {"phone": {}}});
+        let result = call_with_json(
+            register_component,
+            handle,
+            json!(["contact", without_email, {"reject_breaking": true}]),
+        );
+        let RegisterResult::Error {
+            broken_templates, ..
+        } = parse_using_serde(result).unwrap()
+        else {
+            panic!("Expected the breaking schema to be rejected");
+        };
+        assert_eq!(
+            broken_templates.keys().collect::<Vec<_>>(),
+            vec!["profile_view"]
+        );
+        assert_eq!(
+            broken_templates["profile_view"].error_type,
+            CompileErrorType::SchemaValidationError
+        );
+
+        let renderer = get_renderer(handle).unwrap();
+        assert!(renderer
+            .lock()
+            .unwrap()
+            .revalidate_templates("contact")
+            .is_empty());
+
+        let result = call_with_json(
+            register_component,
+            handle,
+            json!(["contact", without_email]),
+        );
+        let RegisterResult::Success { broken_templates } = parse_using_serde(result).unwrap()
+        else {
+            panic!("Expected the schema to be accepted");
+        };
+        assert_eq!(
+            broken_templates.keys().collect::<Vec<_>>(),
+            vec!["profile_view"]
+        );
+    }
+
+    #[test]
+    fn test_register_components_with_cross_references() {
+        let handle = create_renderer();
+        let result = call_with_json(
+            register_components,
+            handle,
+            json!([
+                [
+                    "person",
+                    {
+                        "type": "object",
+                        "properties": {
+                            "name": {"type": "string"},
+                            "address": {"$ref": "address"}
+                        }
+                    }
+                ],
+                [
+                    "address",
+                    {"type": "object", "properties": {"city": {"type": "string"}}}
+                ],
+                ["broken", 5]
+            ]),
+        );
+        let RegisterBatchResult::Batch { results } = parse_using_serde(result).unwrap() else {
+            panic!("Expected batch result");
+        };
+        assert!(matches!(results["person"], RegisterResult::Success { .. }));
+        assert!(matches!(results["address"], RegisterResult::Success { .. }));
+        assert!(matches!(results["broken"], RegisterResult::Error { .. }));
+
+        let templates = template_entities(&[TemplateSource {
+            name: "person_view".to_string(),
+            source: "{{ name }} ({{ address.city }})".to_string(),
+            components: vec![ComponentRef::Union("person".to_string())],
+            output: OutputFormat::Html,
+        }]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+
+        let renderer = get_renderer(handle).unwrap();
+        let renderer = renderer.lock().unwrap();
+        let components = [ComponentRef::Union("person".to_string())];
+        let violations = validate_context(
+            &renderer.components,
+            &components,
+            &json!({"name": "Ann", "address": {"city": 7}}),
+        )
+        .unwrap_err();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].pointer, "/address/city");
+    }
+
+    #[test]
+    fn test_register_components_rejects_duplicate_ids() {
+        let handle = create_renderer();
+        let original = json!({"type": "object", "properties": {"name": {"type": "string"}}});
+        register_test_component(handle, "person", original.clone());
+        let result = call_with_json(
+            register_components,
+            handle,
+            json!([
+                ["person", {"type": "object"}],
+                ["person", {"type": "object", "properties": {}}, {"reject_breaking": true}],
+                ["place", {"type": "object"}]
+            ]),
+        );
+        let RegisterBatchResult::Batch { results } = parse_using_serde(result).unwrap() else {
+            panic!("Expected batch result");
+        };
+        let RegisterResult::Error { message, .. } = &results["person"] else {
+            panic!("Expected the duplicated ID to be rejected");
+        };
+        assert!(message.contains("more than once"), "{message}");
+        assert!(matches!(results["place"], RegisterResult::Success { .. }));
+
+        let renderer = get_renderer(handle).unwrap();
+        let renderer = renderer.lock().unwrap();
+        assert_eq!(renderer.components.get("person"), Some(&original));
+    }
+
+    #[test]
+    fn test_render_error_reports_location_in_included_template() {
+        let handle = create_renderer();
+        let component = (
+            "error_fields".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "count": {"type": "integer"},
+                    "label": {"type": "string"}
+                }
+            }),
+        );
+        let json = serde_json::to_vec(&component).unwrap();
+        parse_using_serde::<RegisterResult>(register_component(handle, json.as_ptr(), json.len()))
+            .unwrap();
+
+        let templates = template_entities(&[
+            TemplateSource {
+                name: "error_child".to_string(),
+                source: "<p>\n  {{ count + label }}\n</p>".to_string(),
+                components: vec![ComponentRef::Union("error_fields".to_string())],
+                output: OutputFormat::Html,
+            },
+            TemplateSource {
+                name: "error_parent".to_string(),
+                source: "{% include 'error_child' %}".to_string(),
+                components: vec![ComponentRef::Union("error_fields".to_string())],
+                output: OutputFormat::Html,
+            },
+        ]);
+        parse_using_serde::<CompileResult>(compile_templates(
+            handle,
+            templates.as_ptr(),
+            templates.len(),
+        ))
+        .unwrap();
+
+        let name = "error_parent";
+        let context_bytes = serde_json::to_vec(&json!({"count": 1, "label": "x"})).unwrap();
+        let result = render_template(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            context_bytes.as_ptr(),
+            context_bytes.len(),
+        );
+        let result: RenderResult = parse_using_serde(result).unwrap();
+
+        match result {
+            RenderResult::Error { error } => {
+                assert_eq!(error.error_type, RenderErrorType::RenderError);
+                assert_eq!(error.kind.as_deref(), Some("InvalidOperation"));
+                assert_eq!(error.template_name.as_deref(), Some("error_child"));
+                assert_eq!(error.line, Some(2));
+                assert_eq!(error.excerpt.as_deref(), Some("  {{ count + label }}"));
+                assert_eq!(error.column_start, Some(6));
+                assert_eq!(error.column_end, Some(19));
+                assert!(!error.causes.is_empty());
+            }
+            RenderResult::Success { result } => {
+                panic!("Expected error result, got success: {}", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_large_output_is_not_truncated() {
+        let handle = create_renderer();
+        let component = (
+            "large_items".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "rows": {"type": "array", "items": {"type": "string"}}
+                }
+            }),
+        );
+        let json = serde_json::to_vec(&component).unwrap();
+        parse_using_serde::<RegisterResult>(register_component(handle, json.as_ptr(), json.len()))
+            .unwrap();
+
+        let templates = template_entities(&[TemplateSource {
+            name: "large".to_string(),
+            source: "{% for row in rows %}<li>{{ row }}</li>{% endfor %}".to_string(),
+            components: vec![ComponentRef::Union("large_items".to_string())],
+            output: OutputFormat::Html,
+        }]);
+        let result = compile_templates(handle, templates.as_ptr(), templates.len());
+        let result: CompileResult = parse_using_serde(result).unwrap();
+        assert!(
+            matches!(result, CompileResult::Success { .. }),
+            "{:?}",
+            result
+        );
+
+        let name = "large";
+        let rows: Vec<String> = (0..5000).map(|i| i.to_string()).collect();
+        let context_bytes = serde_json::to_vec(&json!({ "rows": rows })).unwrap();
+        let result = render_template(
+            handle,
+            name.as_ptr(),
+            name.len(),
+            context_bytes.as_ptr(),
+            context_bytes.len(),
+        );
+        let result: RenderResult = parse_using_serde(result).unwrap();
+        match result {
+            RenderResult::Success { result } => {
+                assert!(result.len() > 50_000);
+                assert!(result.ends_with("<li>4999</li>"));
+            }
+            RenderResult::Error { error } => {
+                panic!("Expected success result, got error: {}", error.message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alloc_and_dealloc_round_trip() {
+        let ptr = alloc(16);
+        assert!(!ptr.is_null());
+        unsafe {
+            std::ptr::write_bytes(ptr, 7, 16);
+            assert_eq!(*ptr.add(15), 7);
+        }
+        dealloc(ptr, 16);
+    }
+
+    #[test]
+    fn test_renderers_are_isolated() {
+        let first = create_renderer();
+        let second = create_renderer();
+
+        for (handle, source) in [(first, "First"), (second, "Second")] {
+            let templates = template_entities(&[TemplateSource {
+                name: "shared_name".to_string(),
+                source: source.to_string(),
+                components: vec![],
+                output: OutputFormat::Html,
+            }]);
+            let result = compile_templates(handle, templates.as_ptr(), templates.len());
+            let result: CompileResult = parse_using_serde(result).unwrap();
+            assert!(matches!(result, CompileResult::Success { .. }));
+        }
+
+        let name = "shared_name";
+        let context_bytes = b"{}";
+        for (handle, expected) in [(first, "First"), (second, "Second")] {
+            let result = render_template(
+                handle,
+                name.as_ptr(),
+                name.len(),
+                context_bytes.as_ptr(),
+                context_bytes.len(),
+            );
+            match parse_using_serde::<RenderResult>(result).unwrap() {
+                RenderResult::Success { result } => assert_eq!(result, expected),
+                RenderResult::Error { error } => {
+                    panic!("Expected success result, got error: {}", error.message)
+                }
+            }
+        }
+
+        assert_eq!(destroy_renderer(first), 1);
+        assert_eq!(destroy_renderer(first), 0);
+        let result = render_template(
+            first,
+            name.as_ptr(),
+            name.len(),
+            context_bytes.as_ptr(),
+            context_bytes.len(),
+        );
+        match parse_using_serde::<RenderResult>(result).unwrap() {
+            RenderResult::Error { error } => {
+                assert_eq!(error.error_type, RenderErrorType::UnknownRenderer)
+            }
+            RenderResult::Success { result } => {
+                panic!("Expected error result, got success: {}", result)
+            }
+        }
+        destroy_renderer(second);
+    }
+
+    #[test]
+    fn test_export_and_import_state() {
+        let handle = create_renderer();
+        register_test_component(
+            handle,
+            "title",
+            json!({"type": "object", "properties": {"title": {"type": "string"}}}),
+        );
+        let entities = serde_json::to_vec(&json!([{
+            TEMPLATE_KEY: {"name": "heading", "source": "<h1>{{ title }}</h1>", "components": ["title"]}
+        }]))
+        .unwrap();
+        let result = compile_templates(handle, entities.as_ptr(), entities.len());
+        let CompileResult::Success { rebuilt } = parse_using_serde(result).unwrap() else {
+            panic!("Expected the template to compile");
+        };
+        assert_eq!(rebuilt, ["heading"]);
+
+        let ptr = export_state(handle);
+        let len = unsafe { u32::from_le_bytes(*(ptr as *const [u8; RESULT_HEADER_LEN])) } as usize;
+        let snapshot = unsafe { slice::from_raw_parts(ptr.add(RESULT_HEADER_LEN), len) }.to_vec();
+        dealloc(ptr, RESULT_HEADER_LEN + len);
+        assert!(snapshot.starts_with(b"LEAF"));
+
+        let restored = create_renderer();
+        let result = import_state(restored, snapshot.as_ptr(), snapshot.len());
+        let result: ImportResult = parse_using_serde(result).unwrap();
+        assert!(matches!(
+            result,
+            ImportResult::Success { revalidated: false }
+        ));
+        let RenderResult::Success { result } =
+            render_test_entity(restored, "heading", json!({"title": {"title": "Hi"}}))
+        else {
+            panic!("Expected the restored template to render");
+        };
+        assert_eq!(result, "<h1>Hi</h1>");
+
+        let result = import_state_validated(restored, snapshot.as_ptr(), snapshot.len());
+        let result: ImportResult = parse_using_serde(result).unwrap();
+        assert!(matches!(
+            result,
+            ImportResult::Success { revalidated: true }
+        ));
+
+        let result = import_state(restored, b"junk".as_ptr(), 4);
+        let result: ImportResult = parse_using_serde(result).unwrap();
+        assert!(matches!(result, ImportResult::Error { .. }));
+
+        destroy_renderer(handle);
+        destroy_renderer(restored);
+    }
+
+    #[test]
+    fn test_export_state_unknown_handle() {
+        let handle = create_renderer();
+        destroy_renderer(handle);
+        let result: ExportResult = parse_using_serde(export_state(handle)).unwrap();
+        let ExportResult::Error { message } = result;
+        assert_eq!(message, format!("Unknown renderer handle {handle}"));
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let handle = create_renderer();
+        let config = json!({"cache_capacity": 4}).to_string();
+        let result = configure_renderer(handle, config.as_ptr(), config.len());
+        parse_using_serde::<ConfigureResult>(result).unwrap();
+        register_test_component(
+            handle,
+            "title",
+            json!({"type": "object", "properties": {"title": {"type": "string"}}}),
+        );
+        let entities = serde_json::to_vec(&json!([{
+            TEMPLATE_KEY: {"name": "heading", "source": "{{ title }}", "components": ["title"]}
+        }]))
+        .unwrap();
+        let result = compile_templates(handle, entities.as_ptr(), entities.len());
+        parse_using_serde::<CompileResult>(result).unwrap();
+        for _ in 0..3 {
+            render_test_entity(handle, "heading", json!({"title": {"title": "Hi"}}));
+        }
+
+        let result: CacheStatsResult = parse_using_serde(cache_stats(handle)).unwrap();
+        let CacheStatsResult::Success { stats } = result else {
+            panic!("Expected cache stats, got {:?}", result);
+        };
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 1, 1));
+
+        destroy_renderer(handle);
+        let result: CacheStatsResult = parse_using_serde(cache_stats(handle)).unwrap();
+        assert!(matches!(result, CacheStatsResult::Error { .. }));
+    }
+
+    #[test]
+    fn test_render_many() {
+        let handle = create_renderer();
+        register_test_component(
+            handle,
+            "greeting",
+            json!({"type": "object", "properties": {"name": {"type": "string"}}}),
+        );
+        let entities = serde_json::to_vec(&json!([{
+            TEMPLATE_KEY: {"name": "hello", "source": "Hi {{ name }}", "components": ["greeting"]}
+        }]))
+        .unwrap();
+        let result = compile_templates(handle, entities.as_ptr(), entities.len());
+        parse_using_serde::<CompileResult>(result).unwrap();
+        let outputs = |result: RenderManyResult| match result {
+            RenderManyResult::Batch { results } => results
+                .into_iter()
+                .map(|result| match result {
+                    RenderResult::Success { result } => result,
+                    RenderResult::Error { error } => format!("{:?}", error.error_type),
+                })
+                .collect::<Vec<_>>(),
+            RenderManyResult::Error { error } => panic!("Expected a batch, got {:?}", error),
+        };
+
+        let request =
+            json!({"template": "hello", "contexts": [{"name": "Ada"}, {}, {"name": "Bo"}]});
+        let result = parse_using_serde(call_with_json(render_many, handle, request)).unwrap();
+        assert_eq!(outputs(result), ["Hi Ada", "RenderError", "Hi Bo"]);
+
+        let request = json!([["missing", {}], ["hello", {"name": "Cy"}]]);
+        let result = parse_using_serde(call_with_json(render_many, handle, request)).unwrap();
+        assert_eq!(outputs(result), ["ParseError", "Hi Cy"]);
+
+        let result = call_with_json(render_many, handle, json!({"template": "hello"}));
+        let result: RenderManyResult = parse_using_serde(result).unwrap();
+        assert!(matches!(result, RenderManyResult::Error { .. }));
+    }
+}
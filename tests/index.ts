@@ -33,10 +33,15 @@ export type JSONSchema =
 
 export type ComponentSchema = [ComponentId, JSONSchemaObject]
 
+export type ComponentRef = ComponentId | { id: ComponentId; as?: string }
+
+export type OutputFormat = "html" | "xml" | "text" | "json" | "markdown"
+
 export interface TemplateSource {
   name: string
   source: string
-  components: ComponentId[]
+  components: ComponentRef[]
+  output?: OutputFormat
   [key: string]: unknown
 }
 
@@ -46,25 +51,103 @@ export interface CompileError {
   missing_dependencies?: string[]
 }
 
+export type TemplateResult =
+  | { type: "NotCommitted" }
+  | { type: "Error"; error: CompileError }
+
 export type CompileResult =
-  | { type: "Success" }
+  | { type: "Success"; rebuilt: string[] }
   | { type: "Error"; error: CompileError }
+  | { type: "Batch"; results: Record<string, TemplateResult> }
+
+export interface ContextViolation {
+  component: ComponentId
+  pointer: string
+  keyword: string
+  message: string
+}
 
 export interface RenderError {
   error_type: string
   message: string
+  violations?: ContextViolation[]
+  missing_components?: ComponentId[]
+  limit?: keyof RenderLimits
+  kind?: string
+  template_name?: string
+  line?: number
+  column_start?: number
+  column_end?: number
+  excerpt?: string
+  causes?: string[]
+}
+
+export interface RegisterOptions {
+  reject_breaking?: boolean
+}
+
+export type RegisterResult =
+  | { type: "Success"; broken_templates?: Record<string, CompileError> }
+  | {
+      type: "Error"
+      message: string
+      broken_templates?: Record<string, CompileError>
+    }
+
+export type RemoveResult =
+  | { type: "Success"; removed_templates: string[] }
+  | { type: "Error"; message: string; dependents?: string[] }
+
+export type ImportResult =
+  | { type: "Success"; revalidated: boolean }
+  | { type: "Error"; message: string; compile_errors?: Record<string, CompileError> }
+
+export interface SanitizeConfig {
+  tags?: string[]
+  generic_attributes?: string[]
+  tag_attributes?: Record<string, string[]>
+  url_schemes?: string[]
+}
+
+export interface RenderLimits {
+  fuel?: number
+  max_recursion?: number
+  max_include_depth?: number
+  max_output_bytes?: number
+  timeout_ms?: number
+}
+
+export interface RendererConfig {
+  validate_context?: boolean
+  sanitize?: SanitizeConfig
+  limits?: RenderLimits
+  cache_capacity?: number
+}
+
+export interface CacheStats {
+  hits: number
+  misses: number
+  entries: number
 }
 
 export type RenderResult =
   | { type: "Success"; result: string }
   | { type: "Error"; error: RenderError }
 
+export interface LeafRendererOptions {
+  // Polled while a template renders. Returning true stops the render with a
+  // `Cancelled` error. Since the render blocks the calling thread, this is
+  // useful when it runs in a worker and reads a flag shared with another
+  // thread, e.g. through `Atomics.load`.
+  shouldCancel?: () => boolean
+}
+
 export class LeafRenderer {
   private wasm: WebAssembly.Instance
   private memory: WebAssembly.Memory
-  private heapOffset = 1024
+  private handle: number
 
-  constructor(wasmModule: WebAssembly.Module) {
+  constructor(wasmModule: WebAssembly.Module, options: LeafRendererOptions = {}) {
     const decoder = new TextDecoder()
     let memory: WebAssembly.Memory
 
@@ -77,6 +160,10 @@ export class LeafRenderer {
           console.log(decoder.decode(new Uint8Array(memory.buffer, ptr, len)))
         },
       },
+      leaf: {
+        now: () => performance.now(),
+        should_cancel: () => (options.shouldCancel?.() ? 1 : 0),
+      },
       ...wasmBindgenImports,
     }
 
@@ -86,98 +173,168 @@ export class LeafRenderer {
     // Get memory export and update closure
     this.memory = this.wasm.exports.memory as WebAssembly.Memory
     memory = this.memory
-  }
 
-  private get memoryBuffer(): Uint8Array {
-    return new Uint8Array(this.memory.buffer)
+    this.handle = (this.wasm.exports.create_renderer as Function)()
   }
 
-  private alloc(size: number): number {
-    const ptr = this.heapOffset
-    this.heapOffset += size
-    this.ensureMemory(ptr + size)
-    return ptr
+  destroy(): void {
+    ;(this.wasm.exports.destroy_renderer as Function)(this.handle)
   }
 
-  private ensureMemory(byteLength: number) {
-    const pagesNeeded = Math.ceil(byteLength / 65536)
-    const currentPages = this.memory.buffer.byteLength / 65536
-    if (pagesNeeded > currentPages) {
-      this.memory.grow(pagesNeeded - currentPages)
-    }
+  // Views must be recreated after every call, since the module may have
+  // grown its memory and detached the previous buffer.
+  private get memoryBuffer(): Uint8Array {
+    return new Uint8Array(this.memory.buffer)
   }
 
   private writeStringToMemory(str: string): [number, number] {
-    const encoder = new TextEncoder()
-    const bytes = encoder.encode(str)
-    const ptr = this.alloc(bytes.length)
+    return this.writeBytesToMemory(new TextEncoder().encode(str))
+  }
+
+  private writeBytesToMemory(bytes: Uint8Array): [number, number] {
+    const ptr = (this.wasm.exports.alloc as Function)(bytes.length)
     this.memoryBuffer.set(bytes, ptr)
     return [ptr, bytes.length]
   }
 
-  private readString(ptr: number, length: number): string {
-    const view = this.memoryBuffer.slice(ptr, ptr + length)
-    return new TextDecoder().decode(view)
+  // Results are returned as a pointer to a buffer owned by the module: a
+  // little-endian u32 length followed by that many bytes of JSON.
+  private readResult(ptr: number): any {
+    return JSON.parse(new TextDecoder().decode(this.readResultBytes(ptr)))
   }
 
-  registerComponent(componentSchema: ComponentSchema): void {
-    const json = JSON.stringify(componentSchema)
-    const [inPtr, inLen] = this.writeStringToMemory(json)
-    const outPtr = this.alloc(4096)
+  private readResultBytes(ptr: number): Uint8Array {
+    const length = new DataView(this.memory.buffer).getUint32(ptr, true)
+    const bytes = this.memoryBuffer.slice(ptr + 4, ptr + 4 + length)
+    ;(this.wasm.exports.dealloc as Function)(ptr, 4 + length)
+    return bytes
+  }
 
-    const resultSize = (this.wasm.exports.register_component as Function)(
-      inPtr,
-      inLen,
-      outPtr,
-      4096
-    )
+  private call(name: string, ...args: string[]): any {
+    const inputs = args.map((arg) => this.writeStringToMemory(arg))
+    try {
+      const resultPtr = (this.wasm.exports[name] as Function)(
+        this.handle,
+        ...inputs.flat()
+      )
+      return this.readResult(resultPtr)
+    } finally {
+      for (const [ptr, len] of inputs) {
+        ;(this.wasm.exports.dealloc as Function)(ptr, len)
+      }
+    }
+  }
 
-    const result = this.readString(outPtr, resultSize)
-    const parsed = JSON.parse(result)
+  configure(config: RendererConfig): void {
+    const parsed = this.call("configure_renderer", JSON.stringify(config))
 
     if (parsed.type === "Error") {
       throw new Error(parsed.message)
     }
   }
 
-  compileTemplates(templates: Entity<TemplateSource>[]): CompileResult {
-    const json = JSON.stringify(templates)
-    const [inPtr, inLen] = this.writeStringToMemory(json)
-    const outPtr = this.alloc(4096)
-
-    const resultSize = (this.wasm.exports.compile_templates as Function)(
-      inPtr,
-      inLen,
-      outPtr,
-      4096
+  // Returns the compiled templates the new schema breaks, keyed by name.
+  registerComponent(
+    componentSchema: ComponentSchema,
+    options: RegisterOptions = {}
+  ): Record<string, CompileError> {
+    const parsed: RegisterResult = this.call(
+      "register_component",
+      JSON.stringify([...componentSchema, options])
     )
 
-    const result = this.readString(outPtr, resultSize)
+    if (parsed.type === "Error") {
+      const broken = Object.keys(parsed.broken_templates ?? {})
+      throw new Error(
+        broken.length ? `${parsed.message}: ${broken.join(", ")}` : parsed.message
+      )
+    }
+    return parsed.broken_templates ?? {}
+  }
+
+  registerComponents(
+    componentSchemas: ComponentSchema[],
+    options: RegisterOptions = {}
+  ): Record<ComponentId, RegisterResult> {
+    const parsed = this.call(
+      "register_components",
+      JSON.stringify(componentSchemas.map((schema) => [...schema, options]))
+    )
+
+    if (parsed.type === "Error") {
+      throw new Error(parsed.message)
+    }
+    return parsed.results
+  }
+
+  unregisterComponent(id: ComponentId, cascade = false): RemoveResult {
+    return this.call("unregister_component", JSON.stringify({ id, cascade }))
+  }
 
-    console.log("Result string:", result)
-    console.log("Memory view:", [
-      ...this.memoryBuffer.slice(outPtr, outPtr + resultSize),
-    ])
+  removeTemplates(names: string[], cascade = false): RemoveResult {
+    return this.call("remove_templates", JSON.stringify({ names, cascade }))
+  }
 
-    return JSON.parse(result)
+  compileTemplates(templates: Entity<TemplateSource>[]): CompileResult {
+    return this.call("compile_templates", JSON.stringify(templates))
   }
 
   renderTemplate(name: string, context: any): RenderResult {
-    const [namePtr, nameLen] = this.writeStringToMemory(name)
-    const contextStr = JSON.stringify(context)
-    const [ctxPtr, ctxLen] = this.writeStringToMemory(contextStr)
-    const outPtr = this.alloc(4096)
-
-    const resultSize = (this.wasm.exports.render_template as Function)(
-      namePtr,
-      nameLen,
-      ctxPtr,
-      ctxLen,
-      outPtr,
-      4096
-    )
+    return this.call("render_template", name, JSON.stringify(context))
+  }
 
-    const result = this.readString(outPtr, resultSize)
-    return JSON.parse(result)
+  renderEntity(name: string, entity: Entity<unknown>): RenderResult {
+    return this.call("render_entity", name, JSON.stringify(entity))
+  }
+
+  // Renders every context in one call to the module, with a result per
+  // render in the same order.
+  renderMany(name: string, contexts: any[]): RenderResult[]
+  renderMany(requests: [string, any][]): RenderResult[]
+  renderMany(nameOrRequests: string | [string, any][], contexts: any[] = []): RenderResult[] {
+    const request =
+      typeof nameOrRequests === "string"
+        ? { template: nameOrRequests, contexts }
+        : nameOrRequests
+    const parsed = this.call("render_many", JSON.stringify(request))
+
+    if (parsed.type === "Error") {
+      throw new Error(parsed.error.message)
+    }
+    return parsed.results
+  }
+
+  cacheStats(): CacheStats {
+    const resultPtr = (this.wasm.exports.cache_stats as Function)(this.handle)
+    const parsed = this.readResult(resultPtr)
+
+    if (parsed.type === "Error") {
+      throw new Error(parsed.message)
+    }
+    return { hits: parsed.hits, misses: parsed.misses, entries: parsed.entries }
+  }
+
+  // Snapshots the registry and compiled templates, for example to cache them
+  // in IndexedDB. The configuration is not included.
+  exportState(): Uint8Array {
+    const resultPtr = (this.wasm.exports.export_state as Function)(this.handle)
+    const bytes = this.readResultBytes(resultPtr)
+
+    // A snapshot starts with "LEAF", and an error result is JSON.
+    if (bytes[0] === "{".charCodeAt(0)) {
+      throw new Error(JSON.parse(new TextDecoder().decode(bytes)).message)
+    }
+    return bytes
+  }
+
+  importState(snapshot: Uint8Array, validate = false): ImportResult {
+    const [ptr, len] = this.writeBytesToMemory(snapshot)
+    const importFn = validate ? "import_state_validated" : "import_state"
+    try {
+      const resultPtr = (this.wasm.exports[importFn] as Function)(this.handle, ptr, len)
+      return this.readResult(resultPtr)
+    } finally {
+      ;(this.wasm.exports.dealloc as Function)(ptr, len)
+    }
   }
 }
@@ -58,7 +58,7 @@ describe("LeafRenderer", () => {
       ]
 
       const result = renderer.compileTemplates(templates)
-      expect(result).toEqual({ type: "Success" })
+      expect(result).toEqual({ type: "Success", rebuilt: ["test1", "test2"] })
     })
 
     it("should handle invalid templates", () => {
@@ -74,28 +74,38 @@ describe("LeafRenderer", () => {
 
       const result = renderer.compileTemplates(templates)
       console.log("result parsed", result)
-      expect(result.type).toBe("Error")
-      if (result.type === "Error") {
-        expect(result.error.error_type).toBe("CompileError")
+      expect(result.type).toBe("Batch")
+      if (result.type === "Batch") {
+        const invalid = result.results["invalid"]
+        expect(invalid.type).toBe("Error")
+        if (invalid.type === "Error") {
+          expect(invalid.error.error_type).toBe("ParseError")
+        }
       }
     })
 
-    // note: we cannot yet inspect template dependencies from the template source
-    // it("should handle missing dependencies", () => {
-    //   const templates: TemplateSource[] = [
-    //     {
-    //       name: "parent",
-    //       source: "{% include 'child' %}",
-    //     },
-    //   ]
+    it("should handle missing dependencies", () => {
+      const templates: Entity<TemplateSource>[] = [
+        {
+          "template:01JVK339CW6Q67VAMXCA7XAK7D": {
+            name: "parent",
+            source: "{% include 'child' %}",
+            components: [],
+          },
+        },
+      ]
 
-    //   const result = renderer.compileTemplates(templates)
-    //   expect(result.type).toBe("Error")
-    //   if (result.type === "Error") {
-    //     expect(result.error.error_type).toBe("MissingDependency")
-    //     expect(result.error.missing_dependencies).toContain("child")
-    //   }
-    // })
+      const result = renderer.compileTemplates(templates)
+      expect(result.type).toBe("Batch")
+      if (result.type === "Batch") {
+        const parent = result.results["parent"]
+        expect(parent.type).toBe("Error")
+        if (parent.type === "Error") {
+          expect(parent.error.error_type).toBe("MissingDependency")
+          expect(parent.error.missing_dependencies).toContain("child")
+        }
+      }
+    })
   })
 
   describe("renderTemplate", () => {